@@ -43,6 +43,8 @@ export default function Admin() {
 	});
 	const [showOutgoingNumbers, setShowOutgoingNumbers] = useState<boolean>(false);
 	const [showIncomingNumbers, setShowIncomingNumbers] = useState<boolean>(false);
+	const [warnings, setWarnings] = useState<string[]>([]);
+	const addWarning = (warning: string) => setWarnings(ws => [...ws.filter(w => w !== warning), warning]);
 
 	// Hide selected numbers 5 seconds after start
 	useEffect(() => {
@@ -61,13 +63,17 @@ export default function Admin() {
 	}, []);
 
 	// The event listeners for the server
-	useEventListener(new URL('events/subscribe?orders_updated', window.location.origin).href, {
+	useEventListener(new URL('events/subscribe?orders_updated&display_disconnected', window.location.origin).href, {
 		'orders_updated': (o) => {
 			const waiting = JSON.parse(o);
 			// Currently assumes multiple admins would have different number namespaces
 			// (i.e., does not synchronize numbers between them)
 			setState(s => ({ ...s, waiting: waiting }));
 		},
+		'display_disconnected': (d) => {
+			const display = JSON.parse(d);
+			addWarning(`Display disconnected: ${display.id}`);
+		},
 	});
 
 	const tellServerAboutOrder = (type: OrderType, number: number) => fetch(`/orders/${number}`, { method: 'POST', body: type }).then(r => r.json()).then(r => setState(s => ({ ...s, waiting: r })));
@@ -85,7 +91,12 @@ export default function Admin() {
 		setShowOutgoingNumbers(true);
 	}
 
-	const tellServerAboutServing = (number: number) => fetch(`/orders/${number}`, { method: 'DELETE' }).then(r => r.json()).then(r => setState(s => ({ ...s, waiting: r })));
+	const tellServerAboutServing = (number: number) => fetch(`/orders/${number}`, { method: 'DELETE' }).then(r => {
+		// The server warns if no display will show the number
+		if (r.headers.has('Warning'))
+			addWarning(`No display connected to show ${number}`);
+		return r.json();
+	}).then(r => setState(s => ({ ...s, waiting: r })));
 
 	const serve = (type: OrderType) => {
 		setState(s => {
@@ -136,6 +147,7 @@ export default function Admin() {
 		<WaitList onClick={console.log} waiting={[...state.waiting]} />
 		<div className={styles.currentNumber}>{state.currentNumber}</div>
 		<Clock className={styles.topLeft} />
+		<Warnings className={styles.topRight} warnings={warnings} onDismiss={() => setWarnings([])} />
 	</div>;
 }
 
@@ -190,6 +202,14 @@ const WaitList = ({ className, waiting, onClick }: { className?: string, waiting
 	</div>
 }
 
+const Warnings = ({ className, warnings, onDismiss }: { className?: string, warnings: string[], onDismiss: () => unknown }) => {
+	if (warnings.length === 0)
+		return null;
+	return <button className={classList(styles.warning, className ?? '')} onClick={() => onDismiss()}>
+		{warnings.map(w => <div key={w}>{w}</div>)}
+	</button>;
+}
+
 const Clock = ({ className }: { className?: string }) => {
 	const [time, setTime] = useState<string>('');
 
@@ -0,0 +1,40 @@
+// Offset of the server clock to the local clock in milliseconds
+let offset = 0;
+
+/**
+ * Estimates the offset to the server clock like NTP,
+ * using the sample with the shortest round trip to be least affected by network delays.
+ * @param samples number of requests to make
+ */
+export const syncClock = async (samples = 5) => {
+	let best: { roundTrip: number, offset: number } | null = null;
+	for (let i = 0; i < samples; i++) {
+		try {
+			const start = Date.now();
+			const server: number = await fetch('/time').then(r => r.json());
+			const end = Date.now();
+			if (best === null || end - start < best.roundTrip)
+				best = { roundTrip: end - start, offset: server - (start + end) / 2 };
+		} catch (e) {
+			console.warn("Failed to synchronize clock:", e);
+		}
+	}
+	if (best !== null)
+		offset = best.offset;
+};
+
+/**
+ * @returns the current server time in milliseconds since the unix epoch
+ */
+export const serverNow = () => Date.now() + offset;
+
+/**
+ * Calls `f` in the first frame after the server time `applyAt`,
+ * or in the next frame if it is missing or already passed.
+ * @param applyAt server time in milliseconds since the unix epoch
+ * @param f function to call
+ */
+export const applyAt = (applyAt: number | undefined, f: () => unknown) => {
+	const delay = applyAt !== undefined ? applyAt - serverNow() : 0;
+	setTimeout(() => requestAnimationFrame(() => f()), Math.max(delay, 0));
+};
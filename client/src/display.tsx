@@ -1,9 +1,10 @@
-import React, { Fragment, useState } from 'react'
+import React, { Fragment, useEffect, useState } from 'react'
 import ReactDOM from 'react-dom/client'
 import styles from './display.module.css'
 import './index.css'
 import { useEventListener } from './eventlistener'
 import { classList } from './utils'
+import { applyAt, syncClock } from './clock'
 
 ReactDOM.createRoot(document.getElementById('root')!).render(
   <React.StrictMode>
@@ -15,39 +16,117 @@ type Image = {
   url: string;
   title: string;
   subtitle: string;
+  type?: 'image' | 'video' | 'html' | 'menu' | 'orders';
+  advance_on_end?: boolean;
+  apply_at?: number;
+  playlist?: string;
+  // Whether to show the order board next to the image
+  order_board?: boolean;
+};
+
+type Order = {
+  type: string;
+  number: number;
+};
+
+// Number of recently served orders shown as ready, like on the server
+const READY_COUNT = 6;
+
+type MenuItem = {
+  name: string;
+  category?: string;
+  description?: string;
+  // In the smallest unit of the currency, e.g. cents
+  price: number;
+  allergens?: string[];
+  sold_out: boolean;
+};
+
+type Menu = {
+  currency: string;
+  items: MenuItem[];
 };
 
 const isImage = (o: unknown): o is Image => {
   return typeof o === "object" && o !== null && "url" in o && typeof o.url === "string" && "title" in o && typeof o.title === "string" && "subtitle" in o && typeof o.subtitle === "string";
 };
 
+type ControlMessage = {
+  command: 'reload' | 'blank' | 'unblank' | 'identify',
+  display: string,
+} | {
+  command: 'navigate',
+  display: string,
+  url: string,
+};
+
+// Remember the id passed as `?display_id=` in a cookie,
+// so the display keeps its identity when reloaded without it
+const displayId = new URLSearchParams(window.location.search).get('display_id');
+if (displayId !== null)
+  document.cookie = `display_id=${displayId}; path=/; max-age=31536000; samesite=lax`;
+
+// Show the playlist passed as `?playlist=` instead of the one of the display group
+const playlist = new URLSearchParams(window.location.search).get('playlist');
+const subscribeUrl = new URL('events/subscribe?image_change&popup_show&popup_hide&display_control&menu_changed&orders_updated&order_served'
+  + (playlist !== null ? `&playlist=${encodeURIComponent(playlist)}` : ''), window.location.origin);
+
+// Keep the clock synchronized with the server, so all displays apply changes at the same time
+syncClock();
+setInterval(syncClock, 60000);
+
 // For customer-facing displays
 export default function Display() {
   const [images, setImages] = useState<[Image, number][]>([]);
   const [popup, setPopup] = useState<{ text: string, show: boolean }>({ text: '', show: false });
+  const [blank, setBlank] = useState<boolean>(false);
+  const [identify, setIdentify] = useState<string | null>(null);
+  const [menu, setMenu] = useState<Menu>({ currency: 'EUR', items: [] });
+  const [waiting, setWaiting] = useState<Order[]>([]);
+  const [ready, setReady] = useState<Order[]>([]);
+
+  useEffect(() => {
+    fetch('/menu').then(r => r.json()).then(setMenu).catch(e => console.warn("Failed to load menu:", e));
+    fetch('/orders/current').then(r => r.json()).then(setWaiting).catch(e => console.warn("Failed to load orders:", e));
+    fetch('/orders/ready').then(r => r.json()).then(setReady).catch(e => console.warn("Failed to load orders:", e));
+  }, []);
+
+  // Hide the name of the display 5 seconds after identifying
+  useEffect(() => {
+    const timeout = setTimeout(() => setIdentify(null), 5000);
+    return () => clearTimeout(timeout);
+  }, [identify]);
 
   /**
    * Adds a new image to the display
    * @param img image to add
    */
   const addImage = async (img: Image) => {
-    // Hack to prefetch image
-    const i = new Image();
-    i.src = img.url;
-    await new Promise((resolve) => i.onload = resolve);
+    if (img.type === 'video') {
+      // Prefetch the start of the video
+      const v = document.createElement('video');
+      v.preload = 'auto';
+      v.src = img.url;
+      await new Promise((resolve) => v.onloadeddata = resolve);
+    } else if (img.type === 'image' || img.type === undefined) {
+      // Hack to prefetch image
+      const i = new Image();
+      i.src = img.url;
+      await new Promise((resolve) => i.onload = resolve);
+    }
 
-    setImages(i => {
+    applyAt(img.apply_at, () => setImages(i => {
       // Use incrementing number as key
       const last = i[i.length - 1];
       const idx = (last !== undefined ? last[1] : -1) + 1;
       const n = i.slice(Math.max(i.length - 2, 0));
       n.push([img, idx]);
       return n;
-    })
+    }));
   };
 
   // The event listeners for the server
-  useEventListener(new URL('events/subscribe?image_change&popup_show&popup_hide', window.location.origin).href, {
+  useEventListener(subscribeUrl.href, {
     'image_change': (d) => {
       try {
         const json = JSON.parse(d);
@@ -58,10 +137,53 @@ export default function Display() {
       }
     },
     'popup_show': (d) => {
-      setPopup({ text: d, show: true });
+      try {
+        const json = JSON.parse(d);
+        applyAt(json.apply_at, () => setPopup({ text: json.text, show: true }));
+      } catch (e) {
+        console.warn("Received invalid popup:", e);
+      }
+    },
+    'popup_hide': (d) => {
+      try {
+        // Hide immediately if no time is given
+        const json = d ? JSON.parse(d) : {};
+        applyAt(json.apply_at, () => setPopup(p => ({ ...p, show: false })));
+      } catch (e) {
+        console.warn("Received invalid popup:", e);
+      }
+    },
+    'menu_changed': (d) => {
+      try {
+        setMenu(JSON.parse(d));
+      } catch (e) {
+        console.warn("Received invalid menu:", e);
+      }
+    },
+    'orders_updated': (d) => {
+      try {
+        setWaiting(JSON.parse(d));
+      } catch (e) {
+        console.warn("Received invalid orders:", e);
+      }
+    },
+    'order_served': (d) => {
+      try {
+        const order: Order = JSON.parse(d);
+        setReady(r => [...r.filter(o => o.number !== order.number), order].slice(-READY_COUNT));
+      } catch (e) {
+        console.warn("Received invalid order:", e);
+      }
     },
-    'popup_hide': () => {
-      setPopup(p => ({ ...p, show: false }));
+    'display_control': (d) => {
+      const message: ControlMessage = JSON.parse(d);
+      switch (message.command) {
+        case 'reload': window.location.reload(); break;
+        case 'blank': setBlank(true); break;
+        case 'unblank': setBlank(false); break;
+        case 'identify': setIdentify(message.display); break;
+        case 'navigate': window.location.href = message.url; break;
+      }
     },
   });
 
@@ -69,16 +191,86 @@ export default function Display() {
     <div className={styles.container}>
       {images.map(([img, key]) => {
         return <Fragment key={key}>
-          <img key={'image' + key} src={img.url} className={styles.backgroundImage} />
+          {img.order_board
+            ? <>
+              <div key={'split' + key} className={styles.splitSlide}><Slide slide={img} menu={menu} waiting={waiting} ready={ready} /></div>
+              <div key={'board' + key} className={styles.splitBoard}><OrderBoard waiting={waiting} ready={ready} /></div>
+            </>
+            : <Slide key={'image' + key} slide={img} menu={menu} waiting={waiting} ready={ready} />}
           <h2 key={'title' + key} className={classList(styles.text, styles.title)} >{img.title}</h2>
           <h2 key={'subtitle' + key} className={classList(styles.text, styles.subtitle)} >{img.subtitle}</h2>
         </Fragment>;
       })}
       <Popup content={popup.text} show={popup.show} />
+      {identify !== null && <div className={classList(styles.text, styles.identify)}>{identify}</div>}
+      {blank && <div className={styles.blank} />}
     </div>
   );
 }
 
+/**
+ * Shows an image, video, HTML page, price board or order board filling the display.
+ * Videos loop unless the slideshow should advance when they end, which is reported to the server.
+ *
+ * @param params The `slide` to show, the current `menu` for price boards and the `waiting` and `ready` orders for order boards
+ * @returns The component
+ */
+const Slide = ({ slide, menu, waiting, ready }: { slide: Image, menu: Menu, waiting: Order[], ready: Order[] }) => {
+  switch (slide.type) {
+    case 'video':
+      return <video src={slide.url} className={styles.backgroundImage} autoPlay muted playsInline loop={!slide.advance_on_end}
+        onEnded={() => fetch(`${slide.playlist !== undefined ? `/playlists/${slide.playlist}` : '/images'}/ended?url=${encodeURIComponent(slide.url)}`, { method: 'POST' })} />;
+    case 'html':
+      return <iframe src={slide.url} className={classList(styles.backgroundImage, styles.frame)} sandbox="allow-scripts" />;
+    case 'menu':
+      return <PriceBoard menu={menu} categories={new URL(slide.url, window.location.origin).searchParams.get('category')?.split(',')} />;
+    case 'orders':
+      return <div className={styles.backgroundImage}><OrderBoard waiting={waiting} ready={ready} /></div>;
+    default:
+      return <img src={slide.url} className={styles.backgroundImage} />;
+  }
+};
+
+/**
+ * Lists the numbers of the ready and the waiting orders.
+ *
+ * @param params The `waiting` and `ready` orders
+ * @returns The component
+ */
+const OrderBoard = ({ waiting, ready }: { waiting: Order[], ready: Order[] }) => {
+  return <div className={styles.orderBoard}>
+    <div className={styles.orderColumn}>
+      <h3>Ready</h3>
+      {ready.slice().reverse().map(o => <div key={o.number} className={styles.readyNumber}>{o.number}</div>)}
+    </div>
+    <div className={styles.orderColumn}>
+      <h3>Waiting</h3>
+      {waiting.map(o => <div key={o.number}>{o.number}</div>)}
+    </div>
+  </div>;
+};
+
+/**
+ * Lists the items of the menu with their prices and allergens, crossing out sold out items.
+ *
+ * @param params The `menu` and the `categories` to show, all if not given
+ * @returns The component
+ */
+const PriceBoard = ({ menu, categories }: { menu: Menu, categories?: string[] }) => {
+  const format = new Intl.NumberFormat(undefined, { style: 'currency', currency: menu.currency });
+  const items = menu.items.filter(i => categories === undefined || (i.category !== undefined && categories.includes(i.category)));
+  return <div className={classList(styles.backgroundImage, styles.priceBoard)}>
+    {items.map((item, i) => <div key={i} className={item.sold_out ? classList(styles.menuItem, styles.soldOut) : styles.menuItem}>
+      <div className={styles.menuName}>
+        {item.name}
+        {item.description && <div className={styles.menuDetails}>{item.description}</div>}
+        {item.allergens && item.allergens.length > 0 && <div className={styles.menuDetails}>{item.allergens.join(', ')}</div>}
+      </div>
+      <div className={styles.menuPrice}>{item.sold_out ? 'Sold out' : format.format(item.price / 10 ** (format.resolvedOptions().maximumFractionDigits ?? 2))}</div>
+    </div>)}
+  </div>;
+};
+
 /**
  * Popup-component that animated based on the `show`-value.
  * Keep in tree at all times set `show` to `false` to hide.
@@ -10,5 +10,7 @@ export const useEventListener = (source: string, listeners: { [key: string]: (da
 		const events = new EventSource(source);
 		Object.entries(listeners).forEach(([key, listener]) => events.addEventListener(key, (e) => listener(e.data)));
 		return () => events.close();
-	});
+		// Only reconnect when the source changes, as every reconnect is tracked by the server
+		// eslint-disable-next-line react-hooks/exhaustive-deps
+	}, [source]);
 }
\ No newline at end of file
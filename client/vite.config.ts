@@ -17,6 +17,12 @@ export default defineConfig({
     proxy: {
       '/orders/': 'http://localhost:8080/',
       '/events/subscribe': 'http://localhost:8080/',
+      '/time': 'http://localhost:8080/',
+      '/images/': 'http://localhost:8080/',
+      '/playlists/': 'http://localhost:8080/',
+      '/menu': 'http://localhost:8080/',
+      '/cache/': 'http://localhost:8080/',
+      '/variants/': 'http://localhost:8080/',
     },
   },
 })
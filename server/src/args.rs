@@ -1,19 +1,68 @@
 use clap::Parser;
 
+use crate::{playlists, settings::PlaybackMode};
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Timeout of images in seconds
+    /// Timeout of images without their own duration in seconds
     #[arg(long, default_value_t = 10)]
     pub image_timeout: u64,
 
-    /// Offset of images
+    /// Offset of images between displays without a configured offset
     #[arg(long, default_value_t = 2)]
     pub image_offset: usize,
 
+    /// The order to show the images in
+    #[arg(long, value_enum, default_value_t = PlaybackMode::Sequential)]
+    pub playback_mode: PlaybackMode,
+
     #[arg(long, default_value = "./images.json")]
     pub image_path: String,
 
+    /// Generate the images from all images in this folder of the assets directory
+    /// instead of reading them from the image path (`.` for the assets directory itself)
+    #[arg(long)]
+    pub image_folder: Option<String>,
+
+    /// The file containing the named playlists and the display groups showing them.
+    /// Without it, all displays show the images of the image path or folder.
+    #[arg(long, default_value = "./playlists.json")]
+    pub playlist_path: String,
+
+    /// Show the board of the waiting and ready orders as every nth slide
+    #[arg(long, conflicts_with = "order_board_split", value_parser = parse_order_board_every)]
+    pub order_board_every: Option<usize>,
+
+    /// Show the board of the waiting and ready orders next to every slide
+    #[arg(long)]
+    pub order_board_split: bool,
+
+    /// The directory to keep local copies of remote images in
+    #[arg(long, default_value = "./cache")]
+    pub cache_dir: String,
+
+    /// The directory to keep images of the assets directory resized for the resolutions of the displays in
+    #[arg(long, default_value = "./variants")]
+    pub variant_dir: String,
+
+    /// The file containing the menu shown on menu slides
+    #[arg(long, default_value = "./menu-items.json")]
+    pub menu_path: String,
+
+    /// Time in milliseconds between sending image changes and popups and the displays applying them,
+    /// so all displays apply them at the same time
+    #[arg(long, default_value_t = 500)]
+    pub apply_delay: u64,
+
+    /// The file to persist settings changed at runtime to, which take precedence over the arguments
+    #[arg(long, default_value = "./settings.json")]
+    pub settings_path: String,
+
+    /// The file containing the configured displays
+    #[arg(long, default_value = "./displays.json")]
+    pub display_path: String,
+
     /// Time to show popups for in seconds
     #[arg(long, default_value_t = 5)]
     pub popup_show: u64,
@@ -22,9 +71,59 @@ pub struct Args {
     #[arg(long, default_value_t = 1)]
     pub popup_wait: u64,
 
+    /// Keep popups queued while no display is connected instead of dropping them
+    #[arg(long)]
+    pub popup_hold: bool,
+
+    /// The file containing the configured outbound webhooks
+    #[arg(long, default_value = "./webhooks.json")]
+    pub webhook_path: String,
+
+    /// The file to keep undelivered webhook events in
+    #[arg(long, default_value = "./webhooks-outbox.json")]
+    pub webhook_outbox_path: String,
+
+    /// Host of the MQTT broker to bridge events and commands to, disables MQTT if not set
+    #[arg(long)]
+    pub mqtt_host: Option<String>,
+
+    #[arg(long, default_value_t = 1883)]
+    pub mqtt_port: u16,
+
+    /// MQTT topic to publish events on, `{topic}` is replaced with the topic of the event
+    #[arg(long, default_value = "pizzplay/events/{topic}")]
+    pub mqtt_event_topic: String,
+
+    /// MQTT topic to receive commands on
+    #[arg(long, default_value = "pizzplay/commands")]
+    pub mqtt_command_topic: String,
+
+    /// The file to write the audit log of all events and order changes to
+    #[arg(long, default_value = "./audit.log")]
+    pub audit_path: String,
+
+    /// Size in bytes after which the audit log is rotated
+    #[arg(long, default_value_t = 10_000_000)]
+    pub audit_max_size: u64,
+
+    /// Number of rotated audit logs to keep
+    #[arg(long, default_value_t = 5)]
+    pub audit_max_files: usize,
+
     /// The directory to serve user-assets (like images) from
     #[arg(long, default_value = "./assets")]
     pub assets_dir: String,
+
+    /// Maximum size of uploaded assets in bytes
+    #[arg(long, default_value_t = 20_000_000)]
+    pub upload_max_size: usize,
+}
+
+fn parse_order_board_every(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())
+        .and_then(playlists::check_order_board_every)
 }
 
 pub fn parse() -> Args {
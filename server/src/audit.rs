@@ -0,0 +1,259 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use crate::{displays, events::Topics};
+
+/// Creates the routes to query the `audit` log.
+///
+/// - `GET /?from=&to=&number=`: All [AuditEntry]s between `from` and `to` (in milliseconds since the unix epoch)
+///   concerning the order `number`. All parameters are optional.
+pub fn routes(audit: Arc<AuditLog>) -> Router {
+    Router::new().route("/", get(query_audit)).with_state(audit)
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// An event sent on a topic
+    Event,
+    /// A change to the orders
+    Mutation,
+}
+
+/// A single entry in the audit log.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AuditEntry {
+    /// Time of the entry in milliseconds since the unix epoch
+    pub timestamp: u128,
+    pub kind: AuditKind,
+    /// The topic of an event or the name of a mutation
+    pub action: String,
+    /// The station or IP address a mutation was made from, `server` for events
+    pub origin: String,
+    /// The order number concerned, if any
+    pub number: Option<u64>,
+    pub payload: serde_json::Value,
+}
+
+impl AuditEntry {
+    /// Creates an entry for a mutation of the order `number`.
+    pub fn mutation(
+        action: &str,
+        origin: &str,
+        number: Option<u64>,
+        payload: serde_json::Value,
+    ) -> Self {
+        Self {
+            timestamp: displays::now(),
+            kind: AuditKind::Mutation,
+            action: action.to_owned(),
+            origin: origin.to_owned(),
+            number,
+            payload,
+        }
+    }
+}
+
+/// A log of all events and mutations, appended to a file as JSON lines.
+///
+/// When the file exceeds its maximum size, it is rotated to `<path>.1`, `<path>.2`, …
+/// keeping at most `max_files` rotated files.
+pub struct AuditLog {
+    path: String,
+    max_size: u64,
+    max_files: usize,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: String, max_size: u64, max_files: usize) -> Self {
+        Self {
+            path,
+            max_size,
+            max_files,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Subscribes to all events to log them.
+    ///
+    /// Must be called after all topics have been registered.
+    pub async fn run(self: &Arc<Self>, topics: &Topics) -> Result<(), String> {
+        let mut events = topics.subscribe(&["*".to_owned()]).await?;
+        let audit = self.clone();
+        tokio::task::spawn(async move {
+            while let Some((topic, data)) = events.recv().await {
+                let payload =
+                    serde_json::from_str(&data).unwrap_or(serde_json::Value::String(data));
+                audit
+                    .log(AuditEntry {
+                        timestamp: displays::now(),
+                        kind: AuditKind::Event,
+                        action: topic,
+                        origin: "server".to_owned(),
+                        number: get_number(&payload),
+                        payload,
+                    })
+                    .await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Appends the `entry` to the log.
+    pub async fn log(&self, entry: AuditEntry) {
+        let _lock = self.lock.lock().await;
+        let Ok(line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        if fs::metadata(&self.path).is_ok_and(|m| m.len() >= self.max_size) {
+            self.rotate();
+        }
+        let r = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{line}"));
+        if r.is_err() {
+            println!("[Warning] Failed to write audit log");
+        }
+    }
+
+    /// Returns all entries between `from` and `to` concerning the order `number`, oldest first.
+    pub async fn query(&self, from: u128, to: u128, number: Option<u64>) -> Vec<AuditEntry> {
+        // Open the files while holding the lock, so they are not rotated in between,
+        // but read them without it, so entries can still be logged meanwhile
+        let files = {
+            let _lock = self.lock.lock().await;
+            (1..=self.max_files)
+                .rev()
+                .map(|i| self.rotated_path(i))
+                .chain([self.path.clone()])
+                .filter_map(|path| File::open(path).ok())
+                .collect::<Vec<_>>()
+        };
+        tokio::task::spawn_blocking(move || read_entries(files, from, to, number))
+            .await
+            .unwrap_or_else(|_| {
+                println!("[Warning] Failed to read audit log");
+                Vec::new()
+            })
+    }
+
+    fn rotated_path(&self, index: usize) -> String {
+        format!("{}.{index}", self.path)
+    }
+
+    /// Moves the current log to `<path>.1`, shifting the older logs and removing the oldest one.
+    fn rotate(&self) {
+        let _ = fs::remove_file(self.rotated_path(self.max_files));
+        for i in (1..self.max_files).rev() {
+            let _ = fs::rename(self.rotated_path(i), self.rotated_path(i + 1));
+        }
+        let r = if self.max_files == 0 {
+            fs::remove_file(&self.path)
+        } else {
+            fs::rename(&self.path, self.rotated_path(1))
+        };
+        if r.is_err() {
+            println!("[Warning] Failed to rotate audit log");
+        }
+    }
+}
+
+/// Reads the entries between `from` and `to` concerning the order `number` from the log `files`, oldest first.
+///
+/// Skips files that only contain entries outside of `from..to`.
+fn read_entries(files: Vec<File>, from: u128, to: u128, number: Option<u64>) -> Vec<AuditEntry> {
+    let mut entries = Vec::new();
+    for file in files {
+        // All entries of the file were logged before it was last modified
+        let modified = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis());
+        if modified.is_some_and(|m| m < from) {
+            continue;
+        }
+        let mut lines = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+            .peekable();
+        // Newer files only contain later entries
+        if lines.peek().is_some_and(|e| e.timestamp > to) {
+            break;
+        }
+        entries.extend(lines.filter(|e| {
+            from <= e.timestamp && e.timestamp <= to && (number.is_none() || e.number == number)
+        }));
+    }
+    entries
+}
+
+/// Gets the order number from an event payload,
+/// which is either an object with a `number` field (as for orders)
+/// or with the number as `text` (as for popups).
+fn get_number(payload: &serde_json::Value) -> Option<u64> {
+    match payload {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.parse().ok(),
+        serde_json::Value::Object(o) => o
+            .get("number")
+            .and_then(|n| n.as_u64())
+            .or_else(|| o.get("text").and_then(|t| t.as_str()?.parse().ok())),
+        _ => None,
+    }
+}
+
+/// The query parameters of the audit log, which are empty when left blank in a form.
+#[derive(Deserialize)]
+struct AuditQuery {
+    from: Option<String>,
+    to: Option<String>,
+    number: Option<String>,
+}
+
+/// Parses the query parameter `name`, treating an empty `value` like a missing one.
+fn parse_param(name: &str, value: Option<&str>) -> Result<Option<u64>, (StatusCode, String)> {
+    match value.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid {name}: {e}"))),
+        None => Ok(None),
+    }
+}
+
+async fn query_audit(
+    State(audit): State<Arc<AuditLog>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let from = parse_param("from", query.from.as_deref())?;
+    let to = parse_param("to", query.to.as_deref())?;
+    let number = parse_param("number", query.number.as_deref())?;
+    Ok(Json(
+        audit
+            .query(
+                from.map_or(0, u128::from),
+                to.map_or(u128::MAX, u128::from),
+                number,
+            )
+            .await,
+    ))
+}
@@ -0,0 +1,157 @@
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Time to wait for a remote slide to download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time to wait before fetching a remote slide again after it failed
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Local copies of remote slides in a directory served at `/cache`,
+/// so displays keep showing them when the internet connection drops.
+///
+/// Cached files are named after the SHA-256 hash of their URL and kept until they are deleted.
+pub struct RemoteCache {
+    directory: PathBuf,
+    client: reqwest::Client,
+    /// Names of the cached files by the hash of their URL
+    files: RwLock<HashMap<String, String>>,
+    /// Hashes of the URLs currently being fetched
+    pending: Mutex<HashSet<String>>,
+    /// Times the fetch of URLs failed by their hash
+    failed: Mutex<HashMap<String, Instant>>,
+}
+
+impl RemoteCache {
+    /// Creates the cache in the `directory`, using the files already cached there.
+    pub fn new(directory: &str) -> Result<Self, String> {
+        let directory = PathBuf::from(directory);
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        let files = fs::read_dir(&directory)
+            .map_err(|e| e.to_string())?
+            .filter_map(|e| {
+                let path = e.ok()?.path();
+                // Skip files that were not completely downloaded
+                if path.extension()? == "part" {
+                    return None;
+                }
+                let hash = path.file_stem()?.to_str()?.to_owned();
+                Some((hash, path.file_name()?.to_str()?.to_owned()))
+            })
+            .collect();
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            directory,
+            client,
+            files: RwLock::new(files),
+            pending: Mutex::new(HashSet::new()),
+            failed: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the URL of the local copy of the `url`, if it is remote and cached.
+    pub fn local_url(&self, url: &str) -> Option<String> {
+        if !is_remote(url) {
+            return None;
+        }
+        let hash = hash(url);
+        let name = self.files.read().ok()?.get(&hash)?.clone();
+        if !self.directory.join(&name).is_file() {
+            // Fetch it again if it was deleted
+            self.files.write().ok()?.remove(&hash);
+            return None;
+        }
+        Some(format!("/cache/{name}"))
+    }
+
+    /// Fetches the remote `urls` that are not cached yet in the background.
+    pub fn prefetch<'a>(self: &Arc<Self>, urls: impl IntoIterator<Item = &'a str>) {
+        for url in urls.into_iter().filter(|u| is_remote(u)) {
+            let hash = hash(url);
+            let cached = self.files.read().is_ok_and(|f| f.contains_key(&hash));
+            let failed_recently = self
+                .failed
+                .lock()
+                .is_ok_and(|f| f.get(&hash).is_some_and(|t| t.elapsed() < RETRY_DELAY));
+            if cached
+                || failed_recently
+                || !self
+                    .pending
+                    .lock()
+                    .is_ok_and(|mut p| p.insert(hash.clone()))
+            {
+                continue;
+            }
+            let cache = self.clone();
+            let url = url.to_owned();
+            tokio::task::spawn(async move {
+                match cache.fetch(&url, &hash).await {
+                    Ok(name) => {
+                        if let Ok(mut files) = cache.files.write() {
+                            files.insert(hash.clone(), name);
+                        }
+                    }
+                    Err(e) => {
+                        println!("[Warning] Failed to cache {url}: {e}");
+                        if let Ok(mut failed) = cache.failed.lock() {
+                            failed.insert(hash.clone(), Instant::now());
+                        }
+                    }
+                }
+                if let Ok(mut pending) = cache.pending.lock() {
+                    pending.remove(&hash);
+                }
+            });
+        }
+    }
+
+    /// Downloads the `url` into the file named after its `hash` and returns the name of the file.
+    async fn fetch(&self, url: &str, hash: &str) -> Result<String, String> {
+        let bytes = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .bytes()
+            .await
+            .map_err(|e| e.to_string())?;
+        // Keep the extension, so the file is served with the right content type
+        let extension = infer::get(&bytes)
+            .map(|k| k.extension().to_owned())
+            .or_else(|| {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                mime_guess::from_path(path).first()?;
+                Some(path.rsplit_once('.')?.1.to_owned())
+            })
+            .unwrap_or_else(|| "bin".to_owned());
+        let name = format!("{hash}.{extension}");
+        // Write to a temporary file first, so displays never get a partial file
+        let part = self.directory.join(format!("{hash}.part"));
+        tokio::fs::write(&part, &bytes)
+            .await
+            .map_err(|e| e.to_string())?;
+        tokio::fs::rename(&part, self.directory.join(&name))
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(name)
+    }
+}
+
+fn is_remote(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn hash(url: &str) -> String {
+    hex::encode(Sha256::digest(url.as_bytes()))
+}
@@ -0,0 +1,339 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::events::{EventAddFunction, Subscriber, Topic, Topics};
+
+/// `Warning` header for responses to requests whose events reached no display.
+pub const NO_DISPLAY_WARNING: (header::HeaderName, &str) =
+    (header::WARNING, "199 - \"No display connected\"");
+
+/// Creates the routes to inspect and control the displays.
+///
+/// - `GET /`: All currently connected displays as a list of [ConnectedDisplay]s
+/// - `POST /control/:command`: Sends a [Command] on the `display_control` topic
+///   to the displays specified by the [Target] in the query parameters.
+///   The `navigate` command takes the URL to navigate to as body.
+///   Responds with the [DeliveryReport](crate::events::DeliveryReport),
+///   adding a `Warning` header if no display received the command.
+pub fn routes(displays: Displays, add_event: Arc<EventAddFunction>, topics: &Topics) -> Router {
+    Router::new()
+        .route("/", get(connected_displays))
+        .route("/control/:command", post(control))
+        .with_state(Arc::new(DisplayState {
+            displays,
+            add_event,
+            display_control: topics.register("display_control"),
+        }))
+}
+
+struct DisplayState {
+    displays: Displays,
+    add_event: Arc<EventAddFunction>,
+    display_control: Topic,
+}
+
+/// Configured properties of a display.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Display {
+    /// Persistent identifier the display subscribes with.
+    /// `None` for displays that did not identify themselves.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Offset of the slideshow on this display in slides
+    #[serde(default)]
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Resolution to send resized variants of the images in, e.g. `1920x1080`
+    #[serde(default)]
+    pub resolution: Option<Resolution>,
+}
+
+/// A resolution in pixels, written as `<width>x<height>`.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid resolution {s:?}, expected e.g. 1920x1080");
+        let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+        let width = width.parse().map_err(|_| invalid())?;
+        let height = height.parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(Resolution { width, height })
+    }
+}
+
+impl TryFrom<String> for Resolution {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Resolution> for String {
+    fn from(resolution: Resolution) -> Self {
+        resolution.to_string()
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// A display currently connected to the event stream.
+#[derive(Serialize, Clone, Debug)]
+pub struct ConnectedDisplay {
+    #[serde(flatten)]
+    pub display: Display,
+    pub user_agent: Option<String>,
+    pub address: SocketAddr,
+    /// Time of connection in milliseconds since the unix epoch
+    pub connected: u128,
+    pub topics: Vec<String>,
+    /// Time of the last event successfully handed to the connection in milliseconds since the unix epoch
+    pub last_send: Option<u128>,
+}
+
+/// All configured and connected displays.
+#[derive(Clone, Default)]
+pub struct Displays {
+    displays: Arc<HashMap<String, Display>>,
+    connected: Arc<Mutex<ConnectedDisplays>>,
+}
+
+#[derive(Default)]
+struct ConnectedDisplays {
+    next_id: u64,
+    displays: BTreeMap<u64, ConnectedDisplay>,
+}
+
+impl Displays {
+    /// Reads the display configuration from a JSON list of [Display]s.
+    pub fn new(displays: &str) -> serde_json::Result<Self> {
+        let displays = serde_json::from_str::<Vec<Display>>(displays)?;
+        Ok(Self {
+            displays: Arc::new(
+                displays
+                    .into_iter()
+                    .filter_map(|d| d.id.clone().map(|id| (id, d)))
+                    .collect(),
+            ),
+            connected: Default::default(),
+        })
+    }
+
+    /// Returns the resolutions of all configured displays.
+    pub fn resolutions(&self) -> HashSet<Resolution> {
+        self.displays
+            .values()
+            .filter_map(|d| d.resolution)
+            .collect()
+    }
+
+    /// Returns the properties of the display with the passed `id`.
+    /// Displays that are not configured get the default properties.
+    pub fn get(&self, id: Option<&str>) -> Display {
+        id.map(|id| {
+            self.displays.get(id).cloned().unwrap_or_else(|| Display {
+                id: Some(id.to_owned()),
+                ..Default::default()
+            })
+        })
+        .unwrap_or_default()
+    }
+
+    /// Adds a display to the connected displays.
+    /// Returns the id of the connection to use with [Displays::disconnect] and [Displays::sent].
+    pub fn connect(&self, display: ConnectedDisplay) -> u64 {
+        let mut connected = self.connected.lock().expect("Display registry poisoned");
+        let id = connected.next_id;
+        connected.next_id += 1;
+        connected.displays.insert(id, display);
+        id
+    }
+
+    /// Removes a display from the connected displays.
+    pub fn disconnect(&self, connection: u64) -> Option<ConnectedDisplay> {
+        self.connected
+            .lock()
+            .expect("Display registry poisoned")
+            .displays
+            .remove(&connection)
+    }
+
+    /// Records a successful send to the connection.
+    pub fn sent(&self, connection: u64) {
+        if let Some(display) = self
+            .connected
+            .lock()
+            .expect("Display registry poisoned")
+            .displays
+            .get_mut(&connection)
+        {
+            display.last_send = Some(now());
+        }
+    }
+
+    /// Returns all connected displays, ordered by time of connection.
+    pub fn connected(&self) -> Vec<ConnectedDisplay> {
+        self.connected
+            .lock()
+            .expect("Display registry poisoned")
+            .displays
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+/// The displays an event is sent to.
+///
+/// Matches all displays if neither `display` nor `group` is set.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Target {
+    /// Only send to the display with this id
+    pub display: Option<String>,
+    /// Only send to displays in this group
+    pub group: Option<String>,
+}
+
+impl Target {
+    pub fn matches(&self, display: &Display) -> bool {
+        self.display
+            .as_ref()
+            .is_none_or(|id| display.id.as_ref() == Some(id))
+            && self
+                .group
+                .as_ref()
+                .is_none_or(|group| display.group.as_ref() == Some(group))
+    }
+
+    /// Returns whether an event is sent to the `subscriber`.
+    /// Subscribers within the server (like the audit log) receive events for all displays.
+    pub fn includes(&self, subscriber: &Subscriber) -> bool {
+        subscriber.internal || self.matches(&subscriber.display)
+    }
+}
+
+/// A command to remotely control displays.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Reload the display, e.g. to apply changed styles
+    Reload,
+    /// Hide all content
+    Blank,
+    /// Show the content again after [Command::Blank]
+    Unblank,
+    /// Flash the name of the display
+    Identify,
+    /// Navigate to another URL
+    Navigate { url: String },
+}
+
+/// The message sent on `display_control` to each display.
+#[derive(Serialize)]
+struct ControlMessage<'a> {
+    #[serde(flatten)]
+    command: &'a Command,
+    /// The name of the receiving display
+    display: String,
+}
+
+/// Reads the `display_id` cookie from the request headers.
+pub fn display_id_from_cookies(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == "display_id")
+        .map(|(_, id)| id.to_owned())
+}
+
+/// Returns the current time in milliseconds since the unix epoch.
+pub fn now() -> u128 {
+    SystemTime::UNIX_EPOCH
+        .elapsed()
+        .unwrap_or_default()
+        .as_millis()
+}
+
+async fn connected_displays(State(state): State<Arc<DisplayState>>) -> impl IntoResponse {
+    Json(state.displays.connected())
+}
+
+async fn control(
+    Path(command): Path<String>,
+    Query(target): Query<Target>,
+    State(state): State<Arc<DisplayState>>,
+    body: String,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let command = match command.as_str() {
+        "reload" => Command::Reload,
+        "blank" => Command::Blank,
+        "unblank" => Command::Unblank,
+        "identify" => Command::Identify,
+        "navigate" if body.is_empty() => {
+            return Err((StatusCode::BAD_REQUEST, "Missing URL".to_owned()))
+        }
+        "navigate" => Command::Navigate { url: body },
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Unknown command '{command}'"),
+            ))
+        }
+    };
+    let report = (state.add_event)(
+        state.display_control.clone(),
+        Box::new(move |s| {
+            if !target.includes(s) {
+                return None;
+            }
+            serde_json::to_string(&ControlMessage {
+                command: &command,
+                display: s
+                    .display
+                    .id
+                    .clone()
+                    .unwrap_or_else(|| format!("#{}", s.index)),
+            })
+            .ok()
+        }),
+    )
+    .await;
+    Ok((
+        AppendHeaders((report.delivered == 0).then_some(NO_DISPLAY_WARNING)),
+        Json(report),
+    ))
+}
@@ -1,124 +1,467 @@
 use axum::{
-    extract::{Query, State},
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::{sse::Event, IntoResponse, Sse},
     routing::get,
     Router,
 };
-use enum_map::{Enum, EnumMap};
 use futures::{future::BoxFuture, lock::Mutex, Stream};
-use serde::Deserialize;
-use std::{sync::Arc, time::Duration};
-use tokio::sync::mpsc::{self, UnboundedSender};
-use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt as _, StreamMap};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_stream::{
+    wrappers::{IntervalStream, UnboundedReceiverStream},
+    StreamExt as _,
+};
+
+use crate::displays::{self, ConnectedDisplay, Display, Displays};
+
+/// The topic every display subscribes to, telling displays apart from other connections
+const DISPLAY_TOPIC: &str = "display_control";
+
+/// Interval in which `heartbeat` events are sent to every connection
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Creates a new server-sent-events system.
 ///
 /// ## Return values
-/// Returns `(router, update_fn)`, where
+/// Returns `(router, update_fn, topics)`, where
 /// - `router`: [axum] [Router] which can be used to allow clients to subscribe to events as `/subscribe`.
-///   Client will be subscribed to all events specified in query parameters.
-pub fn new() -> (Router, EventAddFunction) {
-    let event_channels: EventChannels =
-        Arc::new(EnumMap::from_fn(|_: EventType| Mutex::new(Vec::new())));
+///   Client will be subscribed to all topics specified in query parameters,
+///   either as a list (`?topics=image_change,popup_*`) or as individual parameters (`?image_change&popup_show`).
+///   All other parameters with a value are passed to the topics as [Subscriber::options] (e.g. `?orders_updated&type=pizza`).
+///   Displays identify themselves using the `display_id` query parameter,
+///   or the cookie of the same name if they subscribe to `display_control`.
+///   Every connection receives a `heartbeat` event containing the server time in regular intervals.
+///   When an identified display disconnects, an event is sent on the `display_disconnected` topic.
+/// - `update_fn`: [EventAddFunction] to send an event to all subscribers of a [Topic].
+/// - `topics`: [Topics] registry, in which subsystems register the topics they send events on.
+pub fn new(displays: Displays) -> (Router, EventAddFunction, Topics) {
+    let topics = Topics::default();
     let router = Router::new()
         .route("/subscribe", get(subscribe))
-        .with_state(event_channels.clone());
-    let update_fn: EventAddFunction =
-        Box::new(move |e, mut s: Box<dyn FnMut(usize) -> String + Send>| {
-            let ec = event_channels.clone();
-            Box::pin(async move {
-                let mut channels = ec[e].lock().await;
-                channels.retain(|v| !v.is_closed());
-                let mut error = false;
-                channels.iter().enumerate().for_each(|(idx, channel)| {
-                    let r = channel.send(s(idx));
-                    if r.is_err() {
-                        error = true;
+        .with_state(EventState {
+            display_disconnected: topics.register("display_disconnected"),
+            topics: topics.clone(),
+            displays,
+        });
+    let update_fn: EventAddFunction = Box::new(send_event);
+    (router, update_fn, topics)
+}
+
+/// Sends an event to all subscribers of the topic `t`.
+fn send_event(t: Topic, mut s: EventDataFunction) -> BoxFuture<'static, DeliveryReport> {
+    Box::pin(async move {
+        let mut channels = t.channels.lock().await;
+        channels.retain(|(_, v)| !v.is_closed());
+        let mut report = DeliveryReport::default();
+        let mut index = 0;
+        channels.iter().for_each(|(subscription, channel)| {
+            let subscriber = Subscriber {
+                // Subscribers within the server do not take up a position
+                index: if subscription.internal { 0 } else { index },
+                display: subscription.display.clone(),
+                options: subscription.options.clone(),
+                internal: subscription.internal,
+            };
+            if !subscription.internal {
+                index += 1;
+            }
+            if let Some(data) = s(&subscriber) {
+                let sent = channel.send((t.name().to_owned(), data)).is_ok();
+                if !subscription.internal {
+                    report.targeted += 1;
+                    if sent {
+                        report.delivered += 1;
+                    } else {
+                        report.failed += 1;
                     }
-                });
-                if error {
-                    Err(())
-                } else {
-                    Ok(())
                 }
-            })
+            }
         });
-    (router, update_fn)
+        report
+    })
+}
+
+/// The result of sending an event, only counting displays (not subscribers within the server).
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct DeliveryReport {
+    /// Number of displays the event was meant for
+    pub targeted: usize,
+    /// Number of displays the event was handed to
+    pub delivered: usize,
+    /// Number of displays the event could not be handed to, as they disconnected
+    pub failed: usize,
 }
 
-#[derive(Enum, PartialEq, Eq, Debug)]
-pub enum EventType {
-    ImageChange,
-    PopupShow,
-    PopupHide,
-    OrdersUpdated,
+/// A display subscribed to a [Topic], as passed to the closure of the [EventAddFunction].
+pub struct Subscriber {
+    /// Position of the subscriber in the subscriber list of the topic.
+    /// Changes whenever another subscriber disconnects.
+    pub index: usize,
+    /// The display, which has subscribed
+    pub display: Display,
+    /// The options passed as query parameters when subscribing
+    pub options: Arc<BTreeMap<String, String>>,
+    /// Whether the subscriber is within the server, e.g. the audit log
+    pub internal: bool,
 }
 
-pub type EventAddFunction = Box<
-    dyn Fn(EventType, Box<dyn FnMut(usize) -> String + Send>) -> BoxFuture<'static, Result<(), ()>>
-        + Send
-        + Sync,
->;
+/// A named topic clients can subscribe to.
+///
+/// Can only be obtained by registering it in [Topics].
+#[derive(Clone, Debug)]
+pub struct Topic {
+    name: Arc<str>,
+    channels: Channels,
+}
 
-type EventChannels = Arc<EnumMap<EventType, Mutex<Vec<UnboundedSender<String>>>>>;
+impl Topic {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-#[derive(Deserialize)]
-struct SubscriptionTarget {
-    image_change: Option<String>,
-    popup_show: Option<String>,
-    popup_hide: Option<String>,
-    orders_updated: Option<String>,
+    /// Returns the number of connected displays subscribed to the topic.
+    pub async fn displays(&self) -> usize {
+        self.channels
+            .lock()
+            .await
+            .iter()
+            .filter(|(subscription, channel)| !subscription.internal && !channel.is_closed())
+            .count()
+    }
 }
 
-async fn get_subscribed_streams(
-    event_channels: &EventChannels,
-    subscription_target: &SubscriptionTarget,
-) -> impl Stream<Item = Event> {
-    let mut streams = StreamMap::new();
+impl PartialEq for Topic {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
 
-    for (check, event_type, event_name) in [
-        (
-            &subscription_target.image_change,
-            EventType::ImageChange,
-            "image_change",
-        ),
-        (
-            &subscription_target.popup_show,
-            EventType::PopupShow,
-            "popup_show",
-        ),
-        (
-            &subscription_target.popup_hide,
-            EventType::PopupHide,
-            "popup_hide",
-        ),
-        (
-            &subscription_target.orders_updated,
-            EventType::OrdersUpdated,
-            "orders_updated",
-        ),
-    ] {
-        if check.is_some() {
-            let (sender, receiver) = mpsc::unbounded_channel::<String>();
-            event_channels[event_type].lock().await.push(sender);
-            streams.insert(event_name, UnboundedReceiverStream::new(receiver));
+impl Eq for Topic {}
+
+/// Registry of all topics clients can subscribe to.
+#[derive(Clone, Default)]
+pub struct Topics {
+    topics: Arc<RwLock<BTreeMap<String, Topic>>>,
+}
+
+impl Topics {
+    /// Registers a topic with the passed `name`.
+    /// If the topic is already registered, returns the existing one.
+    pub fn register(&self, name: &str) -> Topic {
+        self.topics
+            .write()
+            .expect("Topic registry poisoned")
+            .entry(name.to_owned())
+            .or_insert_with(|| Topic {
+                name: name.into(),
+                channels: Channels::default(),
+            })
+            .clone()
+    }
+
+    /// Returns all registered topics matching the `pattern`.
+    ///
+    /// The pattern is either the name of a topic
+    /// or contains `*` as a wildcard matching any number of characters.
+    /// Fails if the pattern matches no topic.
+    pub fn resolve(&self, pattern: &str) -> Result<Vec<Topic>, String> {
+        let topics = self.topics.read().expect("Topic registry poisoned");
+        if pattern.contains('*') {
+            let matching = topics
+                .values()
+                .filter(|t| matches_wildcard(pattern, t.name()))
+                .cloned()
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                Err(format!("No topic matches '{pattern}'"))
+            } else {
+                Ok(matching)
+            }
+        } else {
+            topics
+                .get(pattern)
+                .map(|t| vec![t.clone()])
+                .ok_or_else(|| format!("Unknown topic '{pattern}'"))
+        }
+    }
+
+    /// Subscribes to all topics matching the `patterns` from within the server.
+    /// Fails if any pattern matches no topic.
+    ///
+    /// Returns a receiver of `(topic, data)` for every event.
+    pub async fn subscribe(&self, patterns: &[String]) -> Result<EventReceiver, String> {
+        let mut subscribed = Vec::new();
+        for pattern in patterns {
+            subscribed.append(&mut self.resolve(pattern)?);
+        }
+        subscribed.sort_by(|a, b| a.name().cmp(b.name()));
+        subscribed.dedup();
+        Ok(add_subscription(
+            subscribed,
+            Subscription {
+                display: Display::default(),
+                options: Default::default(),
+                internal: true,
+            },
+        )
+        .await)
+    }
+}
+
+/// Sends an event on a [Topic], using the [EventDataFunction] to get the data for every subscriber.
+pub type EventAddFunction =
+    Box<dyn Fn(Topic, EventDataFunction) -> BoxFuture<'static, DeliveryReport> + Send + Sync>;
+
+/// Called for every [Subscriber] of a topic with the data to send to it,
+/// or returns `None` to skip the subscriber.
+pub type EventDataFunction = Box<dyn FnMut(&Subscriber) -> Option<String> + Send>;
+
+/// Receives `(topic, data)` for every event on the subscribed topics.
+pub type EventReceiver = UnboundedReceiver<(String, String)>;
+
+type Channels = Arc<Mutex<Vec<(Subscription, UnboundedSender<(String, String)>)>>>;
+
+/// The parts of a [Subscriber] that stay the same for the whole connection.
+#[derive(Clone)]
+struct Subscription {
+    display: Display,
+    options: Arc<BTreeMap<String, String>>,
+    /// Whether the subscriber is part of the server instead of a connected client
+    internal: bool,
+}
+
+#[derive(Clone)]
+struct EventState {
+    topics: Topics,
+    displays: Displays,
+    display_disconnected: Topic,
+}
+
+/// Removes a connection from the connected displays when its event stream is dropped.
+///
+/// Only identified displays are reported on `display_disconnected`,
+/// as anonymous connections (like admin consoles) cannot be told apart.
+struct ConnectionGuard {
+    displays: Displays,
+    connection: u64,
+    display_disconnected: Topic,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let Some(display) = self.displays.disconnect(self.connection) else {
+            return;
+        };
+        let Some(id) = &display.display.id else {
+            return;
+        };
+        println!("[Warning] Display {id} ({}) disconnected", display.address);
+        let Ok(display_json) = serde_json::to_string(&display) else {
+            return;
+        };
+        // Might be dropped during shutdown of the runtime
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(send_event(
+                self.display_disconnected.clone(),
+                Box::new(move |_| Some(display_json.clone())),
+            ));
+        }
+    }
+}
+
+/// Checks whether `name` matches `pattern`, in which `*` matches any number of characters.
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Resolves all topics requested in the query parameters.
+///
+/// The parameter `topics` contains a comma-separated list of topic patterns,
+/// every other parameter without a value is treated as the name of a topic.
+fn get_requested_topics(
+    topics: &Topics,
+    params: &[(String, String)],
+) -> Result<BTreeMap<String, Topic>, String> {
+    let mut requested = BTreeMap::new();
+    for (key, value) in params {
+        let patterns = if key == "topics" {
+            value.split(',').filter(|p| !p.is_empty()).collect()
+        } else if !value.is_empty() {
+            continue;
+        } else {
+            vec![key.as_str()]
+        };
+        for pattern in patterns {
+            for topic in topics.resolve(pattern)? {
+                requested.insert(topic.name().to_owned(), topic);
+            }
         }
     }
+    if requested.is_empty() {
+        return Err("No topics specified".to_owned());
+    }
+    Ok(requested)
+}
 
-    streams.map(|(name, value)| Event::default().event(name).data(value))
+/// Adds the `subscription` to all `subscribed` topics.
+async fn add_subscription(
+    subscribed: impl IntoIterator<Item = Topic>,
+    subscription: Subscription,
+) -> EventReceiver {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    for topic in subscribed {
+        topic
+            .channels
+            .lock()
+            .await
+            .push((subscription.clone(), sender.clone()));
+    }
+    receiver
+}
+
+async fn get_subscribed_streams(
+    subscribed: BTreeMap<String, Topic>,
+    subscription: Subscription,
+    guard: ConnectionGuard,
+) -> impl Stream<Item = Event> {
+    let events = add_subscription(subscribed.into_values(), subscription).await;
+
+    let heartbeats = IntervalStream::new(tokio::time::interval(HEARTBEAT_INTERVAL))
+        .map(|_| ("heartbeat".to_owned(), displays::now().to_string()));
+
+    // The guard is dropped together with the stream
+    UnboundedReceiverStream::new(events)
+        .merge(heartbeats)
+        .map(move |(name, value)| {
+            guard.displays.sent(guard.connection);
+            Event::default().event(name).data(value)
+        })
 }
 
 async fn subscribe(
-    State(event_channels): State<EventChannels>,
-    Query(subscription_target): Query<SubscriptionTarget>,
-) -> impl IntoResponse {
-    let streams = get_subscribed_streams(&event_channels, &subscription_target)
+    State(state): State<EventState>,
+    Query(params): Query<Vec<(String, String)>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let subscribed =
+        get_requested_topics(&state.topics, &params).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    // The cookie is shared with other pages like the admin console, so only displays are identified by it
+    let is_display = subscribed.contains_key(DISPLAY_TOPIC);
+    let display_id = params
+        .iter()
+        .find(|(key, _)| key == "display_id")
+        .map(|(_, id)| id.clone())
+        .or_else(|| {
+            is_display
+                .then(|| displays::display_id_from_cookies(&headers))
+                .flatten()
+        });
+    let display = state.displays.get(display_id.as_deref());
+    let connection = state.displays.connect(ConnectedDisplay {
+        display: display.clone(),
+        user_agent: headers
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_owned()),
+        address,
+        connected: displays::now(),
+        topics: subscribed.keys().cloned().collect(),
+        last_send: None,
+    });
+    let guard = ConnectionGuard {
+        displays: state.displays.clone(),
+        connection,
+        display_disconnected: state.display_disconnected.clone(),
+    };
+    let subscription = Subscription {
+        display,
+        options: Arc::new(
+            params
+                .into_iter()
+                .filter(|(key, value)| key != "topics" && !value.is_empty())
+                .collect(),
+        ),
+        internal: false,
+    };
+    let streams = get_subscribed_streams(subscribed, subscription, guard)
         .await
         .map(Ok::<_, String>);
 
-    Sse::new(streams).keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_secs(10))
-            .text("keep-alive-text"),
-    )
+    Ok(Sse::new(streams))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(params: &[(&str, &str)]) -> Vec<(String, String)> {
+        params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn wildcard_matches() {
+        assert!(matches_wildcard("popup_*", "popup_show"));
+        assert!(matches_wildcard("*_show", "popup_show"));
+        assert!(matches_wildcard("*", "image_change"));
+        assert!(matches_wildcard("o*_*d", "orders_updated"));
+        assert!(matches_wildcard("popup_show", "popup_show"));
+        assert!(!matches_wildcard("popup_*", "image_change"));
+        assert!(!matches_wildcard("*_hide", "popup_show"));
+        assert!(!matches_wildcard("a*a", "a"));
+    }
+
+    #[test]
+    fn requested_topics() {
+        let topics = Topics::default();
+        for name in ["image_change", "popup_show", "popup_hide", "orders_updated"] {
+            topics.register(name);
+        }
+        let requested = get_requested_topics(
+            &topics,
+            &params(&[
+                ("topics", "popup_*,"),
+                ("image_change", ""),
+                ("type", "pizza"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            requested.keys().collect::<Vec<_>>(),
+            ["image_change", "popup_hide", "popup_show"]
+        );
+    }
+
+    #[test]
+    fn requested_topics_errors() {
+        let topics = Topics::default();
+        topics.register("image_change");
+        assert!(get_requested_topics(&topics, &params(&[("unknown", "")])).is_err());
+        assert!(get_requested_topics(&topics, &params(&[("topics", "popup_*")])).is_err());
+        assert!(get_requested_topics(&topics, &params(&[("type", "pizza")])).is_err());
+        assert!(get_requested_topics(&topics, &params(&[("topics", "")])).is_err());
+    }
 }
@@ -1,97 +1,1472 @@
-use futures::{executor::block_on, lock::Mutex};
-use serde::{Deserialize, Serialize};
-use serde_json;
-use std::{ops::AddAssign, sync::Arc};
-use tokio::{sync::RwLock, time::Interval};
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path as UrlPath, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use futures::lock::Mutex;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{self, Value};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{mpsc::UnboundedReceiver, watch, Notify, RwLock};
 
-use crate::events::{EventAddFunction, EventType};
+use crate::{
+    cache::RemoteCache,
+    displays,
+    events::{EventAddFunction, Topic, Topics},
+    playlists::{OrderBoard, Playlist, Routing, SlideshowOptions},
+    schedule::Schedule,
+    settings::{PlaybackMode, Settings},
+    variants::Variants,
+};
+
+/// Creates the routes to manage the `images`, persisting changes to the file at `path`.
+/// If there is no `path`, as the images are generated, changes are rejected with `409 Conflict`.
+///
+/// - `GET /`: All images as a list of [Image]s
+/// - `POST /`: Adds the [Image] in the body to the end
+/// - `PUT /`: Replaces all images with the list of [Image]s in the body, e.g. to reorder them
+/// - `PUT /:index`: Replaces the image at `index` with the [Image] in the body
+/// - `DELETE /:index`: Removes the image at `index`
+/// - `GET /validate`: The [ValidationReport] of the images in the file at `path`, or of the generated images
+/// - `GET /playback`: The current [Playback] state
+/// - `POST /playback/:command`: Controls the playback with the command `pause`, `resume`, `next` or `previous`
+/// - `POST /playback/jump/:index`: Shows the image at `index`, if it is scheduled for now.
+///   Like all playback commands, sends the resulting image to the displays immediately.
+/// - `POST /ended?url=`: Called by displays when the video at `url` ended,
+///   showing the next image if it is the current one and should advance when it ends
+/// - `POST /upload?append=`: Stores the image or video in the multipart field `file` in the `assets_dir`
+///   and responds with its [Upload]. If `append` is `true`, the image is also added to the end,
+///   using the multipart fields `title` and `subtitle`. Uploads larger than `upload_max_size` bytes are rejected.
+///
+/// All changes respond with the new list of images.
+/// Invalid images are rejected with `422 Unprocessable Entity` and an object mapping each invalid field
+/// (prefixed with the index of the image, e.g. `2.url`, when replacing all images) to its error.
+pub fn routes(
+    images: Arc<Images>,
+    path: Option<String>,
+    assets_dir: String,
+    upload_max_size: usize,
+) -> Router {
+    Router::new()
+        .route("/", get(list_images).post(add_image).put(replace_images))
+        .route("/:index", put(edit_image).delete(remove_image))
+        .route("/validate", get(validate_images))
+        .route("/playback", get(playback))
+        .route("/playback/:command", post(control_playback))
+        .route("/playback/jump/:index", post(jump))
+        .route("/ended", post(video_ended))
+        .route(
+            "/upload",
+            post(upload_image).layer(DefaultBodyLimit::max(upload_max_size)),
+        )
+        .with_state(Arc::new(ImageState {
+            images,
+            path,
+            assets_dir,
+        }))
+}
+
+struct ImageState {
+    images: Arc<Images>,
+    path: Option<String>,
+    assets_dir: String,
+}
+
+/// An uploaded file.
+#[derive(Serialize)]
+struct Upload {
+    /// The URL the file is served from
+    url: String,
+    /// Whether the image was added to the images
+    appended: bool,
+}
+
+#[derive(Deserialize)]
+struct EndedQuery {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    #[serde(default)]
+    append: bool,
+}
+
+/// Errors of invalid fields by their name.
+type FieldErrors = BTreeMap<String, String>;
+
+/// The problems of images that keep them from being shown.
+#[derive(Serialize)]
+struct ValidationReport {
+    /// Number of checked images
+    images: usize,
+    /// Errors of the invalid fields prefixed with the index of the image, e.g. `2.url`,
+    /// or of the whole `file` if it cannot be read
+    errors: FieldErrors,
+}
+
+/// Time to wait after a change to the image file before reloading it,
+/// so multiple writes of a single save are reloaded at once
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+/// Time to show videos advancing when they end for, if they have no duration and no display reports their end
+const MAX_VIDEO_DURATION: Duration = Duration::from_secs(600);
+
+/// Maximum weight of an image, so a single image cannot fill the weighted playlist
+pub const MAX_WEIGHT: u32 = 100;
+
+/// The kind of content shown by a slide.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum MediaType {
+    Image,
+    Video,
+    /// A self-contained HTML page
+    Html,
+    /// A price board of the items of the menu at the URL, e.g. `/menu?category=Pizza`
+    Menu,
+    /// A board of the waiting and ready orders
+    Orders,
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Image {
     url: String,
     title: String,
     subtitle: String,
+    /// Determined from the content or URL if not given
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<MediaType>,
+    /// Whether to show the next image when the video ends instead of after its duration
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    advance_on_end: bool,
+    /// How often to show the image relative to the others in the weighted playback mode, defaults to 1.
+    /// Larger weights are capped at [MAX_WEIGHT].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weight: Option<u32>,
+    #[serde(flatten)]
+    schedule: Schedule,
+}
+
+/// The title, subtitle and playback of a generated image,
+/// read from a JSON file next to it with the same name.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Sidecar {
+    /// Defaults to the file name
+    title: Option<String>,
+    subtitle: String,
+    advance_on_end: bool,
+    weight: Option<u32>,
+    #[serde(flatten)]
+    schedule: Schedule,
+}
+
+/// The data of an `image_change` event.
+#[derive(Serialize)]
+struct ImageChange<'a> {
+    #[serde(flatten)]
+    image: &'a Image,
+    /// Time in milliseconds since the unix epoch at which the displays should show the image
+    apply_at: u128,
+    /// Name of the playlist the image belongs to
+    playlist: &'a str,
+    /// Whether to show the order board next to the image
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    order_board: bool,
 }
 
 pub struct Images {
     images: Arc<RwLock<Vec<Image>>>,
-    timeout: Arc<Mutex<Interval>>,
-    offset: Arc<Mutex<usize>>,
+    /// The image timeout, offset and playback mode
+    settings: watch::Receiver<Settings>,
     current_index: Arc<Mutex<usize>>,
+    order: Mutex<Order>,
+    /// The playlist of the images, whose timing overrides the settings
+    playlist: Playlist,
+    /// Decides which displays show the playlist
+    routing: Arc<Routing>,
     add_event: Arc<EventAddFunction>,
+    image_change: Topic,
+    /// Time in milliseconds between sending and showing an image
+    apply_delay: u64,
+    assets_dir: Arc<str>,
+    /// Local copies of remote images, sent instead of them once fetched
+    cache: Option<Arc<RemoteCache>>,
+    /// Resized images, sent to displays with a resolution instead of the originals once generated
+    variants: Option<Arc<Variants>>,
+    playback: Mutex<Playback>,
+    /// Notified when the playback is changed
+    changed: Notify,
+    video_ended: Notify,
+}
+
+/// The order of the images scheduled for now.
+#[derive(Default)]
+struct Order {
+    mode: PlaybackMode,
+    /// Indices of the scheduled images in all images
+    scheduled: Vec<usize>,
+    /// The number of times all images were shown, to reshuffle them every time
+    cycle: usize,
+    /// Seed of the shuffled order of every cycle, so going back to a cycle shows it in the same order again
+    seed: u64,
+    /// Positions of the images in the scheduled images in the order they are shown
+    positions: Vec<usize>,
+}
+
+/// The state of the slideshow.
+#[derive(Serialize, Clone, Default)]
+struct Playback {
+    paused: bool,
+    /// Index of the image shown on displays without an offset in all images,
+    /// `None` for the order board
+    index: Option<usize>,
+    image: Option<Image>,
+    /// Time the image was shown at in milliseconds since the unix epoch
+    shown_at: u128,
+    /// Time to show the image for in milliseconds
+    duration: u128,
+    /// Whether the next image is shown as soon as the video ends
+    until_end: bool,
 }
 
 impl Images {
-    pub fn new(add_event: Arc<EventAddFunction>, timeout: u64, offset: usize) -> Self {
+    /// Creates the images of the `playlist`, sending them only to the displays showing it according to the `routing`.
+    pub fn new(
+        add_event: Arc<EventAddFunction>,
+        topics: &Topics,
+        playlist: &Playlist,
+        routing: Arc<Routing>,
+        settings: watch::Receiver<Settings>,
+        options: &SlideshowOptions,
+    ) -> Self {
         Self {
             images: Arc::new(RwLock::new(Vec::new())),
             // images: Arc::new(RwLock::new()),
-            timeout: Arc::new(Mutex::new(tokio::time::interval(
-                tokio::time::Duration::from_secs(timeout),
-            ))),
-            offset: Arc::new(Mutex::new(offset)),
+            settings,
             current_index: Arc::new(Mutex::new(0)),
+            order: Mutex::new(Order::default()),
+            playlist: playlist.clone(),
+            routing,
             add_event,
+            image_change: topics.register("image_change"),
+            apply_delay: options.apply_delay,
+            assets_dir: options.assets_dir.as_str().into(),
+            cache: options.cache.clone(),
+            variants: options.variants.clone(),
+            playback: Mutex::new(Playback::default()),
+            changed: Notify::new(),
+            video_ended: Notify::new(),
         }
     }
 
-    pub fn set_images(&mut self, images: &str) -> serde_json::Result<()> {
+    pub async fn set_images(&self, images: &str) -> serde_json::Result<()> {
         let images = serde_json::from_str(images)?;
-        *block_on(self.images.write()) = images;
+        *self.images.write().await = images;
         Ok(())
     }
 
-    pub fn set_offset(&mut self, offset: usize) {
-        *block_on(self.offset.lock()) = offset;
-    }
+    /// Reloads the images whenever the file at `path` changes.
+    /// Keeps the previous images if the file cannot be read or parsed and logs the problems of the new images.
+    pub fn watch(&self, path: String) -> notify::Result<()> {
+        // Watch the directory, as editors often replace the file instead of writing to it
+        let file = Path::new(&path);
+        let name = file.file_name().map(|n| n.to_owned());
+        let directory = file
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let (watcher, mut receiver) = watch_directory(directory)?;
 
-    pub fn set_timeout(&mut self, seconds: u64) {
-        *block_on(self.timeout.lock()) =
-            tokio::time::interval(tokio::time::Duration::from_secs(seconds));
+        let images = self.images.clone();
+        let assets_dir = self.assets_dir.clone();
+        let playlist = self.playlist.name.clone();
+        tokio::task::spawn(async move {
+            // Stop watching when the task ends
+            let _watcher = watcher;
+            while next_change(&mut receiver, |p| p.file_name() == name.as_deref()).await {
+                ValidationReport::from_file(&path, &assets_dir).log(&playlist);
+                let new_images = fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| serde_json::from_str(&data).map_err(|e| e.to_string()));
+                match new_images {
+                    Ok(new_images) => *images.write().await = new_images,
+                    Err(e) => println!(
+                        "[Warning] Failed to reload images from {path}, keeping the previous images: {e}"
+                    ),
+                }
+            }
+        });
+        Ok(())
     }
 
-    pub fn run(&self) -> tokio::task::JoinHandle<()> {
+    /// Generates the images from all images in the `folder` of the `assets_dir`, sorted by name,
+    /// and regenerates them whenever files are added or removed.
+    pub async fn generate(&self, assets_dir: &str, folder: &str) -> notify::Result<()> {
+        let folder = folder.trim_matches('/');
+        let (directory, url_prefix) = match folder {
+            "" | "." => (PathBuf::from(assets_dir), "/assets/".to_owned()),
+            _ => (
+                Path::new(assets_dir).join(folder),
+                format!("/assets/{folder}/"),
+            ),
+        };
+        fs::create_dir_all(&directory).map_err(notify::Error::io)?;
+        *self.images.write().await = generate_images(&directory, &url_prefix);
+        let (watcher, mut receiver) = watch_directory(&directory)?;
+
         let images = self.images.clone();
-        let timeout = self.timeout.clone();
-        let offset = self.offset.clone();
-        let current_index = self.current_index.clone();
-        let add_event = self.add_event.clone();
+        tokio::task::spawn(async move {
+            // Stop watching when the task ends
+            let _watcher = watcher;
+            while next_change(&mut receiver, |_| true).await {
+                *images.write().await = generate_images(&directory, &url_prefix);
+            }
+        });
+        Ok(())
+    }
+
+    /// Validates the images in the file at `path`, or the current images if they are generated.
+    async fn validation_report(&self, path: Option<&str>) -> ValidationReport {
+        match path {
+            Some(path) => ValidationReport::from_file(path, &self.assets_dir),
+            None => {
+                let images = serde_json::to_value(&*self.images.read().await)
+                    .ok()
+                    .and_then(|v| v.as_array().cloned())
+                    .unwrap_or_default();
+                ValidationReport::from_values(&images, &self.assets_dir)
+            }
+        }
+    }
+
+    /// Logs the problems of the images in the file at `path`, or of the current images if they are generated.
+    pub async fn log_problems(&self, path: Option<&str>) {
+        self.validation_report(path).await.log(&self.playlist.name);
+    }
+
+    /// Applies the `change` to the images and saves them to the file at `path`.
+    async fn update<T>(
+        &self,
+        path: Option<&str>,
+        change: impl FnOnce(&mut Vec<Image>) -> Result<T, (StatusCode, String)>,
+    ) -> Result<Json<Vec<Image>>, (StatusCode, String)> {
+        let Some(path) = path else {
+            return Err((
+                StatusCode::CONFLICT,
+                "Images are generated from the assets directory".to_owned(),
+            ));
+        };
+        let mut images = self.images.write().await;
+        change(&mut images)?;
+        let data = serde_json::to_string_pretty(&*images)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        fs::write(path, data).map_err(|e| {
+            println!("[Warning] Failed to save images to {path}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+        Ok(Json(images.clone()))
+    }
+
+    /// Sends the next image to the displays, only rotating through the images scheduled for now.
+    /// Waits for the duration of the image shown on displays without an offset before showing the next one.
+    pub fn run(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let images = self.clone();
+        let mut settings = images.settings.clone();
         tokio::task::spawn(async move {
             loop {
-                {
-                    timeout.lock().await.tick().await
+                let (duration, until_end) = images.show().await;
+                let paused = images.playback.lock().await.paused;
+                let advance = tokio::select! {
+                    _ = tokio::time::sleep(duration), if !paused => true,
+                    _ = images.video_ended.notified(), if until_end && !paused => true,
+                    // Show the current image again after a change of the playback
+                    _ = images.changed.notified() => false,
+                    Ok(()) = settings.changed() => false,
                 };
-                {
-                    let images = (*images.read().await).clone();
-                    let offset = offset.lock().await.clone();
-                    let current = current_index.lock().await.clone();
-                    let _ = add_event(
-                        EventType::ImageChange,
-                        Box::new(move |i| {
-                            get_serialized_image(&images, current, offset, i).unwrap_or_else(|_| {
-                                println!("[Warning] Failed to serialize image");
-                                String::new()
-                            })
-                        }),
-                    )
-                    .await;
-                    current_index.lock().await.add_assign(1);
+                if advance {
+                    images.current_index.lock().await.add_assign(1);
+                }
+            }
+        })
+    }
+
+    /// Sends the current image to the displays, replacing remote images and videos with their cached copy if there is one,
+    /// and returns how long to show it for and whether to advance when the video ends.
+    async fn show(&self) -> (Duration, bool) {
+        let settings = self.settings();
+        let current = *self.current_index.lock().await;
+        let mut playlist = self.playlist(settings.playback_mode, current).await;
+        if let Some(cache) = &self.cache {
+            // HTML pages would miss the resources they load, so only images and videos are cached
+            let cacheable =
+                |i: &Image| matches!(i.media_type, Some(MediaType::Image | MediaType::Video));
+            cache.prefetch(
+                playlist
+                    .iter()
+                    .filter(|(_, i)| cacheable(i))
+                    .map(|(_, i)| i.url.as_str()),
+            );
+            for (_, image) in playlist.iter_mut().filter(|(_, i)| cacheable(i)) {
+                if let Some(url) = cache.local_url(&image.url) {
+                    image.url = url;
+                }
+            }
+        }
+        let (indices, images): (Vec<_>, Vec<_>) = playlist.into_iter().unzip();
+        let position = current % images.len().max(1);
+        let shown = images.get(position);
+        let until_end =
+            shown.is_some_and(|i| i.advance_on_end && i.media_type == Some(MediaType::Video));
+        let duration = match shown.and_then(|i| i.schedule.duration) {
+            Some(seconds) => Duration::from_secs(seconds),
+            None if until_end => MAX_VIDEO_DURATION,
+            None => Duration::from_secs(settings.image_timeout),
+        };
+        {
+            let mut playback = self.playback.lock().await;
+            playback.index = indices.get(position).copied().flatten();
+            playback.image = shown.cloned();
+            playback.shown_at = displays::now();
+            playback.duration = duration.as_millis();
+            playback.until_end = until_end;
+        }
+        let apply_at = displays::now() + u128::from(self.apply_delay);
+        let routing = self.routing.clone();
+        let name = self.playlist.name.clone();
+        let split = matches!(self.playlist.order_board, Some(OrderBoard::Split));
+        let weighted = settings.playback_mode == PlaybackMode::Weighted;
+        // Position of the display among the displays showing the playlist
+        let mut index = 0;
+        let variants = self.variants.clone();
+        let resizable: Vec<_> = images
+            .iter()
+            .filter(|i| i.media_type == Some(MediaType::Image))
+            .map(|i| i.url.clone())
+            .collect();
+        // Resolutions the variants were already generated for
+        let mut prefetched = HashSet::new();
+        let _ = (self.add_event)(
+            self.image_change.clone(),
+            Box::new(move |s| {
+                let position = if s.internal {
+                    0
+                } else if routing.playlist(s) == name {
+                    index += 1;
+                    index - 1
+                } else {
+                    return None;
                 };
+                if images.is_empty() {
+                    return Some(String::new());
+                }
+                // Reduce first, so the offsets of many displays cannot overflow
+                let display_offset = s.display.offset.unwrap_or_else(|| {
+                    (position % images.len()) * (settings.image_offset % images.len())
+                });
+                let image = &images[offset_position(&indices, current, display_offset, weighted)];
+                // Send the variant for the resolution of the display if it is ready
+                let variant = s
+                    .display
+                    .resolution
+                    .zip(variants.as_ref())
+                    .and_then(|(r, v)| {
+                        if prefetched.insert(r) {
+                            v.prefetch(resizable.iter().map(|u| u.as_str()), r);
+                        }
+                        v.url(&image.url, r)
+                    });
+                let variant = variant.map(|url| Image {
+                    url,
+                    ..image.clone()
+                });
+                Some(
+                    get_serialized_image(variant.as_ref().unwrap_or(image), apply_at, &name, split)
+                        .unwrap_or_else(|_| {
+                            println!("[Warning] Failed to serialize image");
+                            String::new()
+                        }),
+                )
+            }),
+        )
+        .await;
+        (duration, until_end)
+    }
+
+    /// Returns the images scheduled for now with their index in all images and their media type determined.
+    async fn scheduled(&self) -> Vec<(usize, Image)> {
+        let now = chrono::Local::now().naive_local();
+        self.images
+            .read()
+            .await
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.schedule.is_active(now))
+            .map(|(index, i)| {
+                let media_type = i
+                    .media_type
+                    .unwrap_or_else(|| get_media_type(&i.url, &self.assets_dir));
+                (
+                    index,
+                    Image {
+                        media_type: Some(media_type),
+                        ..i.clone()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the images scheduled for now in the order they are shown in the `mode`, repeating weighted images,
+    /// with the order board between them if the playlist interleaves it, which has no index in all images.
+    /// Shuffled images are reshuffled when the `current` index starts a new cycle,
+    /// but keep their order when going back to a previous cycle.
+    async fn playlist(&self, mode: PlaybackMode, current: usize) -> Vec<(Option<usize>, Image)> {
+        let scheduled = self.scheduled().await;
+        let indices: Vec<_> = scheduled.iter().map(|(i, _)| *i).collect();
+        let mut order = self.order.lock().await;
+        // A cycle includes the order board, as the current index counts its slides too
+        let cycle = current / slides(self.playlist.order_board, scheduled.len()).max(1);
+        let changed = order.mode != mode || order.scheduled != indices;
+        if changed {
+            order.seed = rand::random();
+        }
+        if changed || (mode == PlaybackMode::Shuffle && order.cycle != cycle) {
+            order.positions = match mode {
+                PlaybackMode::Sequential => (0..scheduled.len()).collect(),
+                PlaybackMode::Shuffle => {
+                    let mut positions: Vec<_> = (0..scheduled.len()).collect();
+                    let seed = order.seed.wrapping_add(cycle as u64);
+                    positions.shuffle(&mut StdRng::seed_from_u64(seed));
+                    positions
+                }
+                PlaybackMode::Weighted => weighted_positions(&scheduled),
+            };
+            order.mode = mode;
+            order.scheduled = indices;
+            order.cycle = cycle;
+        }
+        let images = order
+            .positions
+            .iter()
+            .map(|p| (Some(scheduled[*p].0), scheduled[*p].1.clone()));
+        match self.playlist.order_board {
+            Some(OrderBoard::Every(n)) => {
+                let images: Vec<_> = images.collect();
+                if images.is_empty() {
+                    return vec![(None, order_board())];
+                }
+                images
+                    .chunks(n.max(2) - 1)
+                    .flat_map(|c| c.iter().cloned().chain([(None, order_board())]))
+                    .collect()
+            }
+            _ => images.collect(),
+        }
+    }
+
+    /// Returns the current settings with the timing of the playlist applied.
+    fn settings(&self) -> Settings {
+        self.playlist.timing.apply(*self.settings.borrow())
+    }
+
+    /// Stops rotating the images, keeping the current one.
+    pub async fn pause(&self) {
+        self.playback.lock().await.paused = true;
+        self.changed.notify_one();
+    }
+
+    pub async fn resume(&self) {
+        self.playback.lock().await.paused = false;
+        self.changed.notify_one();
+    }
+
+    pub async fn next(&self) {
+        self.current_index.lock().await.add_assign(1);
+        self.changed.notify_one();
+    }
+
+    pub async fn previous(&self) {
+        let mode = self.settings().playback_mode;
+        let current = *self.current_index.lock().await;
+        let count = self.playlist(mode, current).await.len().max(1);
+        *self.current_index.lock().await = current.checked_sub(1).unwrap_or(count - 1);
+        self.changed.notify_one();
+    }
+
+    /// Shows the image at `index` of all images, if it is scheduled for now.
+    pub async fn jump(&self, index: usize) -> Result<(), (StatusCode, String)> {
+        let mode = self.settings().playback_mode;
+        let current = *self.current_index.lock().await;
+        let playlist = self.playlist(mode, current).await;
+        let Some(position) = playlist.iter().position(|(i, _)| *i == Some(index)) else {
+            return Err(if index < self.images.read().await.len() {
+                (
+                    StatusCode::CONFLICT,
+                    format!("Image {index} is not scheduled for now"),
+                )
+            } else {
+                not_found(index)
+            });
+        };
+        // Stay in the current cycle, so shuffled images are not reshuffled
+        *self.current_index.lock().await = current - current % playlist.len() + position;
+        self.changed.notify_one();
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    /// Validates the images in the file at `path`, even if they cannot be parsed as images.
+    /// A missing file has no images.
+    fn from_file(path: &str, assets_dir: &str) -> Self {
+        let images = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str::<Vec<Value>>(&data).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        };
+        match images {
+            Ok(images) => Self::from_values(&images, assets_dir),
+            Err(e) => Self {
+                images: 0,
+                errors: FieldErrors::from([("file".to_owned(), e)]),
+            },
+        }
+    }
+
+    /// Validates the fields of the `images`
+    /// and that the files in the `assets_dir` they refer to exist and have their type.
+    fn from_values(images: &[Value], assets_dir: &str) -> Self {
+        let mut errors = FieldErrors::new();
+        for (index, value) in images.iter().enumerate() {
+            let prefix = format!("{index}.");
+            match validate(value, &prefix) {
+                Ok(image) => check_asset(&image, assets_dir, &prefix, &mut errors),
+                Err(e) => errors.extend(e),
+            }
+        }
+        Self {
+            images: images.len(),
+            errors,
+        }
+    }
+
+    /// Logs the errors as warnings for the `playlist`.
+    fn log(&self, playlist: &str) {
+        for (field, error) in &self.errors {
+            println!("[Warning] Invalid image {field} in playlist {playlist}: {error}");
+        }
+    }
+}
+
+/// Checks that the file in the `assets_dir` the `image` refers to exists and has the type of the image.
+fn check_asset(image: &Image, assets_dir: &str, prefix: &str, errors: &mut FieldErrors) {
+    let url = image.url.split(['?', '#']).next().unwrap_or_default();
+    let Some(path) = url.strip_prefix("/assets/") else {
+        return;
+    };
+    let file = Path::new(assets_dir).join(path);
+    let error = if !file.is_file() {
+        Some(format!("{path} does not exist in the assets directory"))
+    } else {
+        let kind = infer::get_from_path(&file).ok().flatten();
+        let matcher = kind.map(|k| k.matcher_type());
+        match image
+            .media_type
+            .unwrap_or_else(|| get_media_type(&image.url, assets_dir))
+        {
+            MediaType::Image if matcher != Some(infer::MatcherType::Image) => {
+                Some(format!("{path} is not an image"))
             }
+            MediaType::Video if matcher != Some(infer::MatcherType::Video) => {
+                Some(format!("{path} is not a video"))
+            }
+            _ => None,
+        }
+    };
+    if let Some(error) = error {
+        errors.insert(format!("{prefix}url"), error);
+    }
+}
+
+/// Watches the `directory` for changes, sending the paths of the changed files.
+/// Stops watching when the returned watcher is dropped.
+fn watch_directory(
+    directory: &Path,
+) -> notify::Result<(RecommendedWatcher, UnboundedReceiver<Vec<PathBuf>>)> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() {
+                let _ = sender.send(event.paths);
+            }
+        }
+    })?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    Ok((watcher, receiver))
+}
+
+/// Waits for the next change of a path matching the `filter` and all changes following within [RELOAD_DELAY].
+/// Returns `false` if the watcher stopped.
+async fn next_change(
+    receiver: &mut UnboundedReceiver<Vec<PathBuf>>,
+    filter: impl Fn(&Path) -> bool,
+) -> bool {
+    while let Some(paths) = receiver.recv().await {
+        if paths.iter().any(|p| filter(p)) {
+            tokio::time::sleep(RELOAD_DELAY).await;
+            while receiver.try_recv().is_ok() {}
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns whether a file of the `kind` can be shown as slide.
+fn is_slide(kind: &infer::Type) -> bool {
+    matches!(
+        kind.matcher_type(),
+        infer::MatcherType::Image | infer::MatcherType::Video
+    )
+}
+
+/// Creates an [Image] for every image or video file in the `directory`, sorted by name.
+/// The title and subtitle are read from a [Sidecar] if there is one.
+fn generate_images(directory: &Path, url_prefix: &str) -> Vec<Image> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        println!("[Warning] Failed to read images from {directory:?}");
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            infer::get_from_path(p)
+                .ok()
+                .flatten()
+                .is_some_and(|k| is_slide(&k))
+        })
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let stem = path.file_stem()?.to_str()?;
+            let sidecar = match fs::read_to_string(path.with_extension("json")) {
+                Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                    println!("[Warning] Failed to read title of {name}: {e}");
+                    Sidecar::default()
+                }),
+                Err(_) => Sidecar::default(),
+            };
+            Some(Image {
+                url: format!("{url_prefix}{name}"),
+                title: sidecar.title.unwrap_or_else(|| title_from_file_name(stem)),
+                subtitle: sidecar.subtitle,
+                media_type: None,
+                advance_on_end: sidecar.advance_on_end,
+                weight: sidecar.weight.map(|w| w.clamp(1, MAX_WEIGHT)),
+                schedule: sidecar.schedule,
+            })
         })
+        .collect()
+}
+
+/// Creates a title from a file name like `01_pizza-margherita`,
+/// dropping numbers used for sorting and replacing separators with spaces.
+fn title_from_file_name(stem: &str) -> String {
+    let title = stem
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches(['-', '_', ' '])
+        .replace(['-', '_'], " ");
+    if title.is_empty() {
+        stem.to_owned()
+    } else {
+        title
     }
 }
 
-fn get_serialized_image(
-    images: &Vec<Image>,
+/// Returns the position of the slide a display with the `offset` shows at the `current` index
+/// in a playlist with the `indices` of the images in all images.
+/// Images repeat in the weighted playback mode (`skip_repeated`), so displays with an offset skip ahead
+/// to the next image differing from the one shown on displays without an offset.
+fn offset_position(
+    indices: &[Option<usize>],
     current: usize,
     offset: usize,
-    index: usize,
+    skip_repeated: bool,
+) -> usize {
+    let len = indices.len();
+    let shown = current % len;
+    let position = (shown + offset % len) % len;
+    if !skip_repeated || position == shown {
+        return position;
+    }
+    (0..len)
+        .map(|i| (position + i) % len)
+        .find(|p| indices[*p] != indices[shown])
+        .unwrap_or(position)
+}
+
+/// Returns the number of slides of a playlist showing `images` images, including the interleaved `order_board`.
+fn slides(order_board: Option<OrderBoard>, images: usize) -> usize {
+    match order_board {
+        // The order board follows every chunk of n - 1 images and is shown alone without images
+        Some(OrderBoard::Every(n)) => images + images.div_ceil(n.max(2) - 1).max(1),
+        _ => images,
+    }
+}
+
+/// Spreads the positions of the `scheduled` images over a cycle, repeating each as often as its weight,
+/// using smooth weighted round-robin so repeated images are shown as far apart as possible.
+/// Images are shown equally often if all weights are zero.
+fn weighted_positions(scheduled: &[(usize, Image)]) -> Vec<usize> {
+    let mut weights: Vec<_> = scheduled
+        .iter()
+        .map(|(_, i)| i64::from(i.weight.unwrap_or(1).min(MAX_WEIGHT)))
+        .collect();
+    if weights.iter().all(|w| *w == 0) {
+        weights.fill(1);
+    }
+    let total: i64 = weights.iter().sum();
+    let mut current = vec![0; weights.len()];
+    (0..total)
+        .filter_map(|_| {
+            current.iter_mut().zip(&weights).for_each(|(c, w)| *c += w);
+            let (position, _) = current
+                .iter()
+                .enumerate()
+                .max_by_key(|(p, c)| (**c, -(*p as i64)))?;
+            current[position] -= total;
+            Some(position)
+        })
+        .collect()
+}
+
+/// Determines the type of the content at the `url`,
+/// from the content for files in the `assets_dir` or from the extension otherwise.
+fn get_media_type(url: &str, assets_dir: &str) -> MediaType {
+    if let Some(path) = url.strip_prefix("/assets/") {
+        let kind = infer::get_from_path(Path::new(assets_dir).join(path))
+            .ok()
+            .flatten();
+        match kind.map(|k| k.matcher_type()) {
+            Some(infer::MatcherType::Image) => return MediaType::Image,
+            Some(infer::MatcherType::Video) => return MediaType::Video,
+            _ => {}
+        }
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path {
+        "/menu" => return MediaType::Menu,
+        "/orders/current" => return MediaType::Orders,
+        _ => {}
+    }
+    match mime_guess::from_path(path).first() {
+        Some(mime) if mime.type_() == mime_guess::mime::VIDEO => MediaType::Video,
+        Some(mime) if mime.subtype() == mime_guess::mime::HTML => MediaType::Html,
+        _ => MediaType::Image,
+    }
+}
+
+/// Creates the slide showing the board of the waiting and ready orders.
+fn order_board() -> Image {
+    Image {
+        url: "/orders/current".to_owned(),
+        title: String::new(),
+        subtitle: String::new(),
+        media_type: Some(MediaType::Orders),
+        advance_on_end: false,
+        weight: None,
+        schedule: Schedule::default(),
+    }
+}
+
+/// Serializes the `image` for an `image_change` event.
+fn get_serialized_image(
+    image: &Image,
+    apply_at: u128,
+    playlist: &str,
+    order_board: bool,
 ) -> serde_json::Result<String> {
-    if images.is_empty() {
-        return Ok(String::new());
+    serde_json::to_string(&ImageChange {
+        image,
+        apply_at,
+        playlist,
+        order_board,
+    })
+}
+
+/// Parses an [Image], collecting the errors of all fields prefixed with `prefix`.
+fn validate(value: &Value, prefix: &str) -> Result<Image, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    let Some(object) = value.as_object() else {
+        errors.insert(
+            prefix.trim_end_matches('.').to_owned(),
+            "Must be an object".to_owned(),
+        );
+        return Err(errors);
+    };
+    let mut field = |name: &str, required: bool| match object.get(name) {
+        None => {
+            errors.insert(format!("{prefix}{name}"), "Missing".to_owned());
+            String::new()
+        }
+        Some(Value::String(s)) if required && s.trim().is_empty() => {
+            errors.insert(format!("{prefix}{name}"), "Must not be empty".to_owned());
+            String::new()
+        }
+        Some(Value::String(s)) => s.clone(),
+        Some(_) => {
+            errors.insert(format!("{prefix}{name}"), "Must be a string".to_owned());
+            String::new()
+        }
+    };
+    let url = field("url", true);
+    let title = field("title", false);
+    let subtitle = field("subtitle", false);
+    let e = &mut errors;
+    let media_type = optional_field(object, prefix, "type", e);
+    let advance_on_end = optional_field(object, prefix, "advance_on_end", e).unwrap_or_default();
+    let weight = optional_field(object, prefix, "weight", e);
+    let schedule = Schedule {
+        duration: optional_field(object, prefix, "duration", e),
+        valid_from: optional_field(object, prefix, "valid_from", e),
+        valid_until: optional_field(object, prefix, "valid_until", e),
+        days: optional_field(object, prefix, "days", e).unwrap_or_default(),
+        dayparts: optional_field(object, prefix, "dayparts", e).unwrap_or_default(),
+    };
+    if weight == Some(0) {
+        errors.insert(format!("{prefix}weight"), "Must be positive".to_owned());
+    }
+    if weight.is_some_and(|w| w > MAX_WEIGHT) {
+        errors.insert(
+            format!("{prefix}weight"),
+            format!("Must be at most {MAX_WEIGHT}"),
+        );
+    }
+    if schedule.duration == Some(0) {
+        errors.insert(format!("{prefix}duration"), "Must be positive".to_owned());
+    }
+    if let (Some(from), Some(until)) = (schedule.valid_from, schedule.valid_until) {
+        if until <= from {
+            errors.insert(
+                format!("{prefix}valid_until"),
+                "Must be after valid_from".to_owned(),
+            );
+        }
+    }
+    let image = Image {
+        url,
+        title,
+        subtitle,
+        media_type,
+        advance_on_end,
+        weight,
+        schedule,
+    };
+    if errors.is_empty() {
+        Ok(image)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses the optional field `name` of the `object`, adding an error if it is invalid.
+fn optional_field<T: DeserializeOwned>(
+    object: &serde_json::Map<String, Value>,
+    prefix: &str,
+    name: &str,
+    errors: &mut FieldErrors,
+) -> Option<T> {
+    match serde_json::from_value(object.get(name)?.clone()) {
+        Ok(value) => value,
+        Err(e) => {
+            errors.insert(format!("{prefix}{name}"), e.to_string());
+            None
+        }
+    }
+}
+
+fn invalid(errors: FieldErrors) -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response()
+}
+
+fn not_found(index: usize) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("No image at index {index}"))
+}
+
+async fn list_images(State(state): State<Arc<ImageState>>) -> impl IntoResponse {
+    Json(state.images.images.read().await.clone())
+}
+
+async fn add_image(State(state): State<Arc<ImageState>>, Json(body): Json<Value>) -> Response {
+    let image = match validate(&body, "") {
+        Ok(image) => image,
+        Err(errors) => return invalid(errors),
+    };
+    state
+        .images
+        .update(state.path.as_deref(), |images| {
+            images.push(image);
+            Ok(())
+        })
+        .await
+        .into_response()
+}
+
+async fn replace_images(
+    State(state): State<Arc<ImageState>>,
+    Json(body): Json<Vec<Value>>,
+) -> Response {
+    let mut new_images = Vec::new();
+    let mut errors = FieldErrors::new();
+    for (i, value) in body.iter().enumerate() {
+        match validate(value, &format!("{i}.")) {
+            Ok(image) => new_images.push(image),
+            Err(e) => errors.extend(e),
+        }
+    }
+    if !errors.is_empty() {
+        return invalid(errors);
+    }
+    state
+        .images
+        .update(state.path.as_deref(), |images| {
+            *images = new_images;
+            Ok(())
+        })
+        .await
+        .into_response()
+}
+
+async fn edit_image(
+    UrlPath(index): UrlPath<usize>,
+    State(state): State<Arc<ImageState>>,
+    Json(body): Json<Value>,
+) -> Response {
+    let image = match validate(&body, "") {
+        Ok(image) => image,
+        Err(errors) => return invalid(errors),
+    };
+    state
+        .images
+        .update(state.path.as_deref(), |images| {
+            *images.get_mut(index).ok_or_else(|| not_found(index))? = image;
+            Ok(())
+        })
+        .await
+        .into_response()
+}
+
+async fn remove_image(
+    UrlPath(index): UrlPath<usize>,
+    State(state): State<Arc<ImageState>>,
+) -> impl IntoResponse {
+    state
+        .images
+        .update(state.path.as_deref(), |images| {
+            if index >= images.len() {
+                return Err(not_found(index));
+            }
+            Ok(images.remove(index))
+        })
+        .await
+}
+
+async fn upload_image(
+    State(state): State<Arc<ImageState>>,
+    Query(query): Query<UploadQuery>,
+    mut multipart: Multipart,
+) -> Response {
+    let mut file = None;
+    let mut title = String::new();
+    let mut subtitle = String::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (e.status(), e.body_text()).into_response(),
+        };
+        let name = field.name().unwrap_or_default().to_owned();
+        let file_name = field.file_name().unwrap_or_default().to_owned();
+        let data = match field.bytes().await {
+            Ok(data) => data,
+            Err(e) => return (e.status(), e.body_text()).into_response(),
+        };
+        match name.as_str() {
+            "file" => file = Some((file_name, data)),
+            "title" => title = String::from_utf8_lossy(&data).into_owned(),
+            "subtitle" => subtitle = String::from_utf8_lossy(&data).into_owned(),
+            _ => {}
+        }
+    }
+    let Some((file_name, data)) = file else {
+        return (StatusCode::BAD_REQUEST, "Missing field 'file'").into_response();
+    };
+    // Only trust the content, not the name or content type sent by the client
+    let Some(kind) = infer::get(&data).filter(is_slide) else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Only images and videos can be uploaded",
+        )
+            .into_response();
+    };
+    if query.append && state.path.is_none() {
+        return (
+            StatusCode::CONFLICT,
+            "Images are generated from the assets directory",
+        )
+            .into_response();
+    }
+    let name = match store_asset(&state.assets_dir, &file_name, kind.extension(), &data) {
+        Ok(name) => name,
+        Err(e) => {
+            println!("[Warning] Failed to store upload in {}", state.assets_dir);
+            return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    let url = format!("/assets/{name}");
+    if query.append {
+        let image = Image {
+            url: url.clone(),
+            title,
+            subtitle,
+            media_type: None,
+            advance_on_end: false,
+            weight: None,
+            schedule: Schedule::default(),
+        };
+        let r = state
+            .images
+            .update(state.path.as_deref(), |images| {
+                images.push(image);
+                Ok(())
+            })
+            .await;
+        if let Err(e) = r {
+            return e.into_response();
+        }
+    }
+    Json(Upload {
+        url,
+        appended: query.append,
+    })
+    .into_response()
+}
+
+/// Stores the `data` in the `assets_dir` under a new name based on the `original` file name
+/// and returns that name.
+fn store_asset(
+    assets_dir: &str,
+    original: &str,
+    extension: &str,
+    data: &[u8],
+) -> std::io::Result<String> {
+    // Keep the name recognizable, but without any characters that could escape the directory
+    let stem: String = Path::new(original)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .take(64)
+        .collect();
+    let stem = if stem.is_empty() { "upload" } else { &stem };
+    fs::create_dir_all(assets_dir)?;
+    let timestamp = displays::now();
+    for attempt in 0.. {
+        let name = match attempt {
+            0 => format!("{stem}-{timestamp}.{extension}"),
+            _ => format!("{stem}-{timestamp}-{attempt}.{extension}"),
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(Path::new(assets_dir).join(&name))
+        {
+            Ok(mut file) => return file.write_all(data).map(|_| name),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+async fn video_ended(
+    State(state): State<Arc<ImageState>>,
+    Query(query): Query<EndedQuery>,
+) -> impl IntoResponse {
+    let playback = state.images.playback.lock().await;
+    if playback.until_end && playback.image.as_ref().is_some_and(|i| i.url == query.url) {
+        state.images.video_ended.notify_waiters();
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn validate_images(State(state): State<Arc<ImageState>>) -> impl IntoResponse {
+    Json(state.images.validation_report(state.path.as_deref()).await)
+}
+
+async fn playback(State(state): State<Arc<ImageState>>) -> impl IntoResponse {
+    Json(state.images.playback.lock().await.clone())
+}
+
+async fn control_playback(
+    UrlPath(command): UrlPath<String>,
+    State(state): State<Arc<ImageState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    match command.as_str() {
+        "pause" => state.images.pause().await,
+        "resume" => state.images.resume().await,
+        "next" => state.images.next().await,
+        "previous" => state.images.previous().await,
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Unknown command '{command}'"),
+            ))
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn jump(
+    UrlPath(index): UrlPath<usize>,
+    State(state): State<Arc<ImageState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    state.images.jump(index).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weighted(weights: &[Option<u32>]) -> Vec<(usize, Image)> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| {
+                let image = serde_json::json!({
+                    "url": format!("/assets/{index}.jpg"),
+                    "title": "",
+                    "subtitle": "",
+                    "weight": weight,
+                });
+                (index, serde_json::from_value(image).unwrap())
+            })
+            .collect()
+    }
+
+    fn errors(value: Value, prefix: &str) -> Vec<(String, String)> {
+        validate(&value, prefix).unwrap_err().into_iter().collect()
+    }
+
+    #[test]
+    fn validate_image() {
+        let image = validate(
+            &serde_json::json!({"url": "/assets/a.jpg", "title": "A", "subtitle": "", "weight": 2}),
+            "",
+        )
+        .unwrap();
+        assert_eq!(image.url, "/assets/a.jpg");
+        assert_eq!(image.weight, Some(2));
+    }
+
+    #[test]
+    fn validate_missing_or_empty_url() {
+        assert_eq!(
+            errors(serde_json::json!({"title": "", "subtitle": ""}), ""),
+            [("url".to_owned(), "Missing".to_owned())]
+        );
+        assert_eq!(
+            errors(
+                serde_json::json!({"url": " ", "title": "", "subtitle": ""}),
+                ""
+            ),
+            [("url".to_owned(), "Must not be empty".to_owned())]
+        );
+    }
+
+    #[test]
+    fn validate_wrong_type() {
+        assert_eq!(
+            errors(
+                serde_json::json!({"url": "/a.jpg", "title": 1, "subtitle": ""}),
+                ""
+            ),
+            [("title".to_owned(), "Must be a string".to_owned())]
+        );
+    }
+
+    #[test]
+    fn validate_prefixes_fields() {
+        assert_eq!(
+            errors(serde_json::json!({"title": "", "subtitle": ""}), "2."),
+            [("2.url".to_owned(), "Missing".to_owned())]
+        );
+        assert_eq!(
+            errors(serde_json::json!("/a.jpg"), "2."),
+            [("2".to_owned(), "Must be an object".to_owned())]
+        );
+    }
+
+    #[test]
+    fn validate_validity() {
+        let image = |until: &str| {
+            serde_json::json!({
+                "url": "/a.jpg",
+                "title": "",
+                "subtitle": "",
+                "valid_from": "2024-06-01T18:00:00",
+                "valid_until": until,
+            })
+        };
+        assert_eq!(
+            errors(image("2024-06-01T18:00:00"), ""),
+            [(
+                "valid_until".to_owned(),
+                "Must be after valid_from".to_owned()
+            )]
+        );
+        assert_eq!(
+            errors(image("2024-06-01T12:00:00"), ""),
+            [(
+                "valid_until".to_owned(),
+                "Must be after valid_from".to_owned()
+            )]
+        );
+        assert!(validate(&image("2024-06-01T18:00:01"), "").is_ok());
+    }
+
+    /// Creates an empty directory for a test, removing what a previous run left.
+    fn test_dir(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("pizzplay-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn store_asset_sanitizes_name() {
+        let directory = test_dir("sanitize");
+        let assets_dir = directory.to_str().unwrap();
+        let name = store_asset(assets_dir, "../../etc/passwd", "jpg", b"a").unwrap();
+        assert!(name.starts_with("passwd-") && name.ends_with(".jpg"));
+        assert_eq!(fs::read(directory.join(&name)).unwrap(), b"a");
+        let name = store_asset(assets_dir, "../ä ö.png", "png", b"a").unwrap();
+        assert!(name.starts_with("upload-"));
+        let name = store_asset(assets_dir, "", "png", b"a").unwrap();
+        assert!(name.starts_with("upload-"));
+        assert!(fs::read_dir(&directory)
+            .unwrap()
+            .all(|e| e.unwrap().path().parent() == Some(directory.as_path())));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn store_asset_keeps_existing() {
+        let directory = test_dir("existing");
+        let assets_dir = directory.to_str().unwrap();
+        // Take the names of the next second, so the name without suffix already exists
+        fs::create_dir_all(&directory).unwrap();
+        let now = displays::now();
+        for timestamp in now..now + 1000 {
+            fs::write(directory.join(format!("menu-{timestamp}.jpg")), b"old").unwrap();
+        }
+        let name = store_asset(assets_dir, "menu.jpg", "jpg", b"new").unwrap();
+        assert!(
+            name.starts_with("menu-") && name.ends_with("-1.jpg"),
+            "{name}"
+        );
+        assert_eq!(fs::read(directory.join(&name)).unwrap(), b"new");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn slides_with_order_board() {
+        assert_eq!(slides(None, 4), 4);
+        assert_eq!(slides(Some(OrderBoard::Split), 4), 4);
+        assert_eq!(slides(Some(OrderBoard::Every(3)), 4), 6);
+        assert_eq!(slides(Some(OrderBoard::Every(3)), 5), 8);
+        assert_eq!(slides(Some(OrderBoard::Every(2)), 3), 6);
+        assert_eq!(slides(Some(OrderBoard::Every(3)), 0), 1);
+    }
+
+    #[test]
+    fn weighted_positions_repeat_by_weight() {
+        assert_eq!(
+            weighted_positions(&weighted(&[Some(3), None, None])),
+            [0, 1, 0, 2, 0]
+        );
+        assert_eq!(weighted_positions(&weighted(&[None, None])), [0, 1]);
+    }
+
+    #[test]
+    fn weighted_positions_spread_repeats() {
+        let positions = weighted_positions(&weighted(&[Some(2), Some(2), Some(1)]));
+        assert_eq!(positions.len(), 5);
+        assert!(positions.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn weighted_positions_skip_zero_weight() {
+        assert_eq!(weighted_positions(&weighted(&[Some(0), Some(2)])), [1, 1]);
+        assert_eq!(weighted_positions(&weighted(&[Some(0), Some(0)])), [0, 1]);
+        assert!(weighted_positions(&[]).is_empty());
+    }
+
+    #[test]
+    fn weighted_positions_cap_weight() {
+        let positions = weighted_positions(&weighted(&[Some(u32::MAX), None]));
+        assert_eq!(positions.len(), MAX_WEIGHT as usize + 1);
+    }
+
+    #[test]
+    fn validate_weight() {
+        let image = |weight: u32| serde_json::json!({"url": "/a.jpg", "title": "", "subtitle": "", "weight": weight});
+        assert!(validate(&image(MAX_WEIGHT), "").is_ok());
+        assert_eq!(
+            errors(image(MAX_WEIGHT + 1), ""),
+            [("weight".to_owned(), format!("Must be at most {MAX_WEIGHT}"))]
+        );
+        assert_eq!(
+            errors(image(0), ""),
+            [("weight".to_owned(), "Must be positive".to_owned())]
+        );
+    }
+
+    #[test]
+    fn offset_position_skips_repeated_images() {
+        let indices: Vec<_> = weighted_positions(&weighted(&[Some(3), None, None]))
+            .into_iter()
+            .map(Some)
+            .collect();
+        assert_eq!(indices, [Some(0), Some(1), Some(0), Some(2), Some(0)]);
+        // Without skipping, the display with offset 2 would show image 0 like the one without an offset
+        assert_eq!(offset_position(&indices, 0, 2, false), 2);
+        assert_eq!(offset_position(&indices, 0, 2, true), 3);
+        for current in 0..indices.len() {
+            for offset in 1..indices.len() {
+                let position = offset_position(&indices, current, offset, true);
+                assert_ne!(indices[position], indices[current], "{current} {offset}");
+            }
+            assert_eq!(offset_position(&indices, current, 0, true), current);
+        }
+        assert_eq!(offset_position(&indices, usize::MAX, usize::MAX, false), 0);
     }
-    let idx = (current + index * offset) % images.len();
-    let image = &images[idx];
-    serde_json::to_string(image)
 }
@@ -1,87 +1,232 @@
-use axum::{http::StatusCode, routing::get, Router};
-use events::EventType;
-use futures::lock::Mutex;
-use images::Images;
+use audit::AuditLog;
+use axum::{
+    extract::Query,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use cache::RemoteCache;
+use displays::{Displays, Target};
+use menu::Menu;
+use mqtt::MqttSettings;
+use orders::Orders;
+use playlists::{OrderBoard, Playlist, SlideshowOptions};
 use popups::Popups;
+use settings::Settings;
 use std::{
     fs,
     net::{IpAddr, Ipv6Addr, SocketAddr},
     sync::Arc,
     time::SystemTime,
 };
+use tokio::sync::watch;
 use tower_http::services::ServeDir;
+use variants::Variants;
+use webhooks::Webhooks;
 
 mod args;
+mod audit;
+mod cache;
 mod client;
+mod displays;
 mod events;
 mod images;
+mod menu;
+mod mqtt;
 mod orders;
+mod playlists;
 mod popups;
+mod schedule;
+mod settings;
+mod variants;
+mod webhooks;
 
 #[tokio::main]
 async fn main() {
     let args = args::parse();
 
-    let (event_routes, event_sender) = events::new();
+    let display_data = fs::read_to_string(args.display_path).unwrap_or("[]".to_owned());
+    let displays = Displays::new(&display_data)
+        .unwrap_or_else(|_| panic!("Failed to read display data: {display_data:?}"));
+    let (event_routes, event_sender, topics) = events::new(displays.clone());
     let event_sender = Arc::new(event_sender);
-    let mut images = Images::new(event_sender.clone(), args.image_timeout, args.image_offset);
-    let image_data = fs::read_to_string(args.image_path).unwrap_or("[]".to_owned());
-    images
-        .set_images(&image_data)
-        .expect(format!("Failed to read image data: {image_data:?}").as_str());
-    images.run();
-    let popups = Arc::new(Mutex::new(Popups::new(
-        event_sender.clone(),
-        args.popup_show,
-        args.popup_wait,
-    )));
-    {
-        popups.lock().await.run();
+    let settings = Settings::load(
+        &args.settings_path,
+        Settings {
+            image_timeout: args.image_timeout,
+            image_offset: args.image_offset,
+            popup_show: args.popup_show,
+            popup_wait: args.popup_wait,
+            playback_mode: args.playback_mode,
+        },
+    );
+    let (settings_sender, settings_receiver) = watch::channel(settings);
+    let playlists = match fs::read_to_string(&args.playlist_path) {
+        Ok(playlist_data) => playlists::parse(&playlist_data)
+            .unwrap_or_else(|e| panic!("Failed to read playlists: {e}")),
+        Err(_) => vec![Playlist {
+            name: "default".to_owned(),
+            groups: Vec::new(),
+            path: Some(args.image_path),
+            folder: args.image_folder,
+            order_board: match (args.order_board_every, args.order_board_split) {
+                (Some(n), _) => Some(OrderBoard::Every(n)),
+                (None, true) => Some(OrderBoard::Split),
+                (None, false) => None,
+            },
+            timing: Default::default(),
+        }],
     };
+    let variants = Arc::new(Variants::new(
+        &args.assets_dir,
+        &args.variant_dir,
+        displays.resolutions(),
+    ));
+    let cache = RemoteCache::new(&args.cache_dir)
+        .map_err(|e| {
+            println!("[Warning] Failed to create cache, showing remote images directly: {e}")
+        })
+        .ok();
+    let playlist_routes = playlists::start(
+        playlists,
+        event_sender.clone(),
+        &topics,
+        settings_receiver.clone(),
+        SlideshowOptions {
+            apply_delay: args.apply_delay,
+            assets_dir: args.assets_dir.clone(),
+            upload_max_size: args.upload_max_size,
+            cache: cache.map(Arc::new),
+            variants: Some(variants.clone()),
+        },
+    )
+    .await;
+    let popups = Arc::new(Popups::new(
+        event_sender.clone(),
+        &topics,
+        settings_receiver,
+        args.popup_hold,
+        args.apply_delay,
+    ));
+    popups.run();
+    let menu = Menu::new(event_sender.clone(), &topics, args.menu_path)
+        .unwrap_or_else(|e| panic!("Failed to read menu: {e}"));
+    let audit = Arc::new(AuditLog::new(
+        args.audit_path,
+        args.audit_max_size,
+        args.audit_max_files,
+    ));
+    let orders = Arc::new(Orders::new(
+        event_sender.clone(),
+        &topics,
+        popups,
+        audit.clone(),
+    ));
     let es1 = event_sender.clone();
     let es2 = event_sender.clone();
+    let image_change = topics.register("image_change");
+    let popup_show = topics.register("popup_show");
+    let popup_hide = topics.register("popup_hide");
     let routes = Router::new()
         .merge(client::client_handler(Some("index.html")))
         .nest("/events", event_routes)
-        .nest("/orders", orders::routes(event_sender.clone(), popups))
+        .nest(
+            "/displays",
+            displays::routes(displays, event_sender.clone(), &topics),
+        )
+        .merge(playlist_routes)
+        .nest("/orders", orders::routes(orders.clone()))
+        .nest("/menu", menu::routes(Arc::new(menu)))
+        .nest("/audit", audit::routes(audit.clone()))
+        .nest(
+            "/settings",
+            settings::routes(settings_sender, args.settings_path, args.popup_hold),
+        )
+        .route("/time", get(current_time))
         .nest_service("/assets", ServeDir::new(args.assets_dir))
+        .nest_service("/cache", ServeDir::new(args.cache_dir))
+        .nest("/variants", variants::routes(variants))
         .route(
             "/test-event",
             get(move || async move {
-                event_sender(
-                    EventType::ImageChange,
-                    Box::new(|i| {
-                        format!(
+                let report = event_sender(
+                    image_change.clone(),
+                    Box::new(|s| {
+                        Some(format!(
                             "{{
                                 \"url\": \"https://picsum.photos/1920/{}\",
                                 \"title\": \"Test\",
                                 \"subtitle\": \"This is a test from the server\"
                             }}",
                             1080 + (SystemTime::UNIX_EPOCH.elapsed().unwrap().as_millis()
-                                + i as u128 % 100)
-                        )
+                                + s.index as u128 % 100)
+                        ))
                     }),
                 )
-                .await
+                .await;
+                Json(report)
             }),
         )
         .route(
             "/test-show",
-            get(|| async move {
-                es1(
-                    EventType::PopupShow,
-                    Box::new(|i| format!("Popup from Server ({})", i)),
+            get(|Query(target): Query<Target>| async move {
+                let report = es1(
+                    popup_show.clone(),
+                    Box::new(move |s| {
+                        target
+                            .includes(s)
+                            .then(|| {
+                                serde_json::json!({ "text": format!("Popup from Server ({})", s.index) })
+                                    .to_string()
+                            })
+                    }),
                 )
-                .await
+                .await;
+                Json(report)
             }),
         )
         .route(
             "/test-hide",
-            get(|| async move { es2(EventType::PopupHide, Box::new(|_| String::new())).await }),
+            get(|| async move {
+                Json(es2(popup_hide.clone(), Box::new(|_| Some(String::new()))).await)
+            }),
         )
         .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") });
 
+    // All topics are registered now
+    audit.run(&topics).await.expect("Failed to start audit log");
+    let webhook_data = fs::read_to_string(args.webhook_path).unwrap_or("[]".to_owned());
+    let webhooks = Webhooks::new(&webhook_data, &args.webhook_outbox_path)
+        .unwrap_or_else(|e| panic!("Failed to read webhook data: {e}"));
+    webhooks
+        .run(&topics)
+        .await
+        .expect("Failed to start webhooks");
+    if let Some(host) = args.mqtt_host {
+        let settings = MqttSettings {
+            host,
+            port: args.mqtt_port,
+            event_topic: args.mqtt_event_topic,
+            command_topic: args.mqtt_command_topic,
+        };
+        mqtt::run(settings, &topics, orders)
+            .await
+            .expect("Failed to start MQTT bridge");
+    }
+
     let addr = &SocketAddr::new(IpAddr::from(Ipv6Addr::UNSPECIFIED), 8080);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, routes).await.unwrap();
+    axum::serve(
+        listener,
+        routes.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+}
+
+/// The current server time in milliseconds since the unix epoch,
+/// for displays to estimate the offset of their clock.
+async fn current_time() -> impl IntoResponse {
+    ([(header::CACHE_CONTROL, "no-store")], Json(displays::now()))
 }
@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, sync::Arc};
+
+use crate::events::{EventAddFunction, Topic, Topics};
+
+/// Creates the routes to manage the `menu`.
+///
+/// - `GET /?category=`: The [MenuDefinition], with only the items of the comma-separated `category` list if given
+/// - `PUT /`: Replaces the menu with the [MenuDefinition] in the body.
+///   Items without a name are rejected with `422 Unprocessable Entity` and an object mapping each invalid field to its error.
+/// - `PUT /:index/sold_out`: Marks the item at `index` as sold out or available again with `true` or `false` in the body
+///
+/// All changes respond with the new menu.
+pub fn routes(menu: Arc<Menu>) -> Router {
+    Router::new()
+        .route("/", get(get_menu).put(replace_menu))
+        .route("/:index/sold_out", put(set_sold_out))
+        .with_state(menu)
+}
+
+/// The menu shown on menu slides.
+///
+/// Example: `{"currency": "EUR", "items": [{"name": "Margherita", "category": "Pizza", "price": 850, "allergens": ["gluten", "lactose"]}]}`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MenuDefinition {
+    /// ISO 4217 code of the currency of the prices
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    pub items: Vec<MenuItem>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MenuItem {
+    pub name: String,
+    /// The category the item is listed under, e.g. `Pizza`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Price in the smallest unit of the currency, e.g. cents
+    pub price: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<String>,
+    #[serde(default)]
+    pub sold_out: bool,
+}
+
+#[derive(Deserialize)]
+struct MenuQuery {
+    category: Option<String>,
+}
+
+fn default_currency() -> String {
+    "EUR".to_owned()
+}
+
+/// The menu, sent as full [MenuDefinition] on `menu_changed` whenever it changes.
+pub struct Menu {
+    menu: Mutex<MenuDefinition>,
+    path: String,
+    add_event: Arc<EventAddFunction>,
+    menu_changed: Topic,
+}
+
+impl Menu {
+    /// Creates the menu from the file at `path`, which changes are saved to.
+    /// The menu is empty if there is no file.
+    pub fn new(
+        add_event: Arc<EventAddFunction>,
+        topics: &Topics,
+        path: String,
+    ) -> serde_json::Result<Self> {
+        let menu = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(_) => MenuDefinition {
+                currency: default_currency(),
+                items: Vec::new(),
+            },
+        };
+        Ok(Self {
+            menu: Mutex::new(menu),
+            path,
+            add_event,
+            menu_changed: topics.register("menu_changed"),
+        })
+    }
+
+    /// Applies the `change` to the menu, saves it and sends it to the displays.
+    async fn update(
+        &self,
+        change: impl FnOnce(&mut MenuDefinition) -> Result<(), (StatusCode, String)>,
+    ) -> Result<Json<MenuDefinition>, (StatusCode, String)> {
+        let mut menu = self.menu.lock().await;
+        change(&mut menu)?;
+        let data = serde_json::to_string_pretty(&*menu)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if fs::write(&self.path, data).is_err() {
+            println!("[Warning] Failed to save menu to {}", self.path);
+        }
+        let data = serde_json::to_string(&*menu)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let _ = (self.add_event)(
+            self.menu_changed.clone(),
+            Box::new(move |_| Some(data.clone())),
+        )
+        .await;
+        Ok(Json(menu.clone()))
+    }
+}
+
+async fn get_menu(
+    State(menu): State<Arc<Menu>>,
+    Query(query): Query<MenuQuery>,
+) -> impl IntoResponse {
+    let mut menu = menu.menu.lock().await.clone();
+    if let Some(categories) = query.category {
+        let categories = categories.split(',').collect::<Vec<_>>();
+        menu.items.retain(|i| {
+            i.category
+                .as_deref()
+                .is_some_and(|c| categories.contains(&c))
+        });
+    }
+    Json(menu)
+}
+
+async fn replace_menu(
+    State(menu): State<Arc<Menu>>,
+    Json(new_menu): Json<MenuDefinition>,
+) -> Response {
+    let errors = new_menu
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| i.name.trim().is_empty())
+        .map(|(index, _)| (format!("items.{index}.name"), "Must not be empty"))
+        .collect::<BTreeMap<_, _>>();
+    if !errors.is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response();
+    }
+    menu.update(|m| {
+        *m = new_menu;
+        Ok(())
+    })
+    .await
+    .into_response()
+}
+
+async fn set_sold_out(
+    State(menu): State<Arc<Menu>>,
+    Path(index): Path<usize>,
+    Json(sold_out): Json<bool>,
+) -> Result<Json<MenuDefinition>, (StatusCode, String)> {
+    menu.update(|m| match m.items.get_mut(index) {
+        Some(item) => {
+            item.sold_out = sold_out;
+            Ok(())
+        }
+        None => Err((StatusCode::NOT_FOUND, format!("No item {index}"))),
+    })
+    .await
+}
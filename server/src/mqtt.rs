@@ -0,0 +1,127 @@
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+
+use crate::{events::Topics, orders::Orders};
+
+/// Time to wait before reconnecting after the connection to the broker failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings of the connection to the MQTT broker.
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    /// MQTT topic to publish events on, `{topic}` is replaced with the topic of the event
+    pub event_topic: String,
+    /// MQTT topic to receive [Command]s on
+    pub command_topic: String,
+}
+
+/// A command received on the command topic as JSON, e.g. `{"command": "serve", "number": 12}`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    /// Creates an order, like `POST /orders/:id`
+    Create {
+        number: u64,
+        #[serde(rename = "type")]
+        order_type: String,
+    },
+    /// Serves an order, like `DELETE /orders/:id`
+    Serve { number: u64 },
+    /// Cancels an order, like `POST /orders/:id/cancel`
+    Cancel { number: u64 },
+    /// Shows a popup with the text
+    Announce { text: String },
+}
+
+/// Bridges the events to an MQTT broker.
+///
+/// Every event is published to the broker and [Command]s received from it are executed on the `orders`.
+/// Reconnects automatically if the connection to the broker is lost.
+/// Events are dropped while too many are waiting to be published.
+///
+/// Must be called after all topics have been registered.
+pub async fn run(
+    settings: MqttSettings,
+    topics: &Topics,
+    orders: Arc<Orders>,
+) -> Result<(), String> {
+    let mut events = topics.subscribe(&["*".to_owned()]).await?;
+    let mut options = MqttOptions::new("pizzplay", settings.host, settings.port);
+    options.set_keep_alive(Duration::from_secs(10));
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+
+    // Publish events, dropping them while the broker is unreachable, as they would pile up otherwise
+    let publisher = client.clone();
+    let event_topic = settings.event_topic;
+    tokio::task::spawn(async move {
+        let mut dropping = false;
+        while let Some((topic, data)) = events.recv().await {
+            let r = publisher.try_publish(
+                event_topic.replace("{topic}", &topic),
+                QoS::AtLeastOnce,
+                false,
+                data,
+            );
+            match r {
+                Ok(()) => dropping = false,
+                Err(_) if !dropping => {
+                    println!("[Warning] Failed to publish events to MQTT, dropping them until the broker is reachable");
+                    dropping = true;
+                }
+                Err(_) => {}
+            }
+        }
+    });
+
+    // Receive commands
+    let command_topic = settings.command_topic;
+    tokio::task::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    // Subscriptions are lost when reconnecting
+                    if client
+                        .subscribe(&command_topic, QoS::AtLeastOnce)
+                        .await
+                        .is_err()
+                    {
+                        println!("[Warning] Failed to subscribe to MQTT topic {command_topic}");
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    match serde_json::from_slice::<Command>(&publish.payload) {
+                        Ok(command) => execute(command, &orders).await,
+                        Err(e) => println!("[Warning] Received invalid MQTT command: {e}"),
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    println!("[Warning] MQTT connection failed: {e}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// The origin of commands received over MQTT in the audit log
+const ORIGIN: &str = "mqtt";
+
+async fn execute(command: Command, orders: &Orders) {
+    match command {
+        Command::Create { number, order_type } => {
+            orders.create(number, order_type, ORIGIN).await;
+        }
+        Command::Serve { number } => {
+            orders.serve(number, ORIGIN).await;
+        }
+        Command::Cancel { number } => {
+            orders.cancel(number, ORIGIN).await;
+        }
+        Command::Announce { text } => orders.announce(text, ORIGIN).await,
+    }
+}
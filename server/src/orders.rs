@@ -1,13 +1,15 @@
 use std::{
     error::Error,
     fs,
+    net::SocketAddr,
     sync::Arc,
     time::{Duration, SystemTime},
 };
 
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    response::{AppendHeaders, IntoResponse},
     routing::{delete, get, post},
     Json, Router,
 };
@@ -16,97 +18,295 @@ use futures::lock::Mutex;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    events::{EventAddFunction, EventType},
+    audit::{AuditEntry, AuditLog},
+    displays::NO_DISPLAY_WARNING,
+    events::{EventAddFunction, Subscriber, Topic, Topics},
     popups::Popups,
 };
 
 #[derive(Serialize, Deserialize, Clone)]
-struct Order {
+pub struct Order {
     pub timestamp: u128,
     #[serde(rename = "type")]
     pub order_type: String,
     pub number: u64,
 }
 
-struct OrderState {
+/// Changes to the current orders, as sent on `orders_diff`.
+#[derive(Serialize)]
+struct OrdersDiff<'a> {
+    added: Vec<&'a Order>,
+    removed: Vec<&'a Order>,
+}
+
+/// Number of recently served orders shown as ready on the order board
+const READY_COUNT: usize = 6;
+
+/// The current and all past orders.
+pub struct Orders {
     add_event: Arc<EventAddFunction>,
-    popups: Arc<Mutex<Popups>>,
+    orders_updated: Topic,
+    orders_diff: Topic,
+    order_created: Topic,
+    order_served: Topic,
+    order_cancelled: Topic,
+    popups: Arc<Popups>,
+    audit: Arc<AuditLog>,
     current: Arc<Mutex<Vec<Order>>>,
     all: Arc<Mutex<Vec<Order>>>,
+    /// The most recently served orders, the latest last
+    ready: Mutex<Vec<Order>>,
 }
 
-pub fn routes(add_event: Arc<EventAddFunction>, popups: Arc<Mutex<Popups>>) -> Router {
-    let (current, all) = load().unwrap_or_else(|_| (Vec::new(), Vec::new()));
+impl Orders {
+    /// Creates the orders, restoring them from disk if possible.
+    ///
+    /// Changes are sent as the full list of current orders on `orders_updated`
+    /// and as the added and removed orders on `orders_diff`.
+    /// Subscribers can filter both by passing a comma-separated list of order types as `type` option.
+    /// Additionally, every single order is sent on `order_created`, `order_served` or `order_cancelled`.
+    /// All changes are recorded in the `audit` log with their origin.
+    pub fn new(
+        add_event: Arc<EventAddFunction>,
+        topics: &Topics,
+        popups: Arc<Popups>,
+        audit: Arc<AuditLog>,
+    ) -> Self {
+        let (current, all) = load().unwrap_or_else(|_| (Vec::new(), Vec::new()));
+        Self {
+            add_event,
+            orders_updated: topics.register("orders_updated"),
+            orders_diff: topics.register("orders_diff"),
+            order_created: topics.register("order_created"),
+            order_served: topics.register("order_served"),
+            order_cancelled: topics.register("order_cancelled"),
+            popups,
+            audit,
+            current: Arc::new(Mutex::new(current)),
+            all: Arc::new(Mutex::new(all)),
+            ready: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a new order with the number `id`, made from `origin`.
+    /// Returns the current orders.
+    pub async fn create(&self, id: u64, order_type: String, origin: &str) -> Vec<Order> {
+        let order = Order {
+            timestamp: SystemTime::UNIX_EPOCH
+                .elapsed()
+                .unwrap_or(Duration::default())
+                .as_millis(),
+            order_type,
+            number: id,
+        };
+        {
+            self.all.lock().await.push(order.clone());
+        };
+        let mut current_state = self.current.lock().await;
+        let all = self.all.lock().await;
+        current_state.push(order.clone());
+        self.audit
+            .log(AuditEntry::mutation(
+                "create",
+                origin,
+                Some(id),
+                serde_json::to_value(&order).unwrap_or_default(),
+            ))
+            .await;
+        self.send_order_events(&self.order_created, std::slice::from_ref(&order))
+            .await;
+        self.send_updates(&current_state, vec![order], Vec::new())
+            .await;
+        save(&current_state, &all);
+        current_state.clone()
+    }
+
+    /// Serves the order with the number `id` and announces it as popup, made from `origin`.
+    /// Returns the current orders.
+    pub async fn serve(&self, id: u64, origin: &str) -> Vec<Order> {
+        self.announce(id.to_string(), origin).await;
+        let (current, served) = self.remove(id, &self.order_served, "serve", origin).await;
+        let mut ready = self.ready.lock().await;
+        ready.extend(served);
+        let excess = ready.len().saturating_sub(READY_COUNT);
+        ready.drain(..excess);
+        current
+    }
+
+    /// Cancels the order with the number `id` without announcing it, made from `origin`.
+    /// Returns the current orders.
+    pub async fn cancel(&self, id: u64, origin: &str) -> Vec<Order> {
+        self.remove(id, &self.order_cancelled, "cancel", origin)
+            .await
+            .0
+    }
+
+    /// Shows the `text` as popup on the displays, requested from `origin`.
+    pub async fn announce(&self, text: String, origin: &str) {
+        self.audit
+            .log(AuditEntry::mutation(
+                "announce",
+                origin,
+                text.parse().ok(),
+                serde_json::Value::String(text.clone()),
+            ))
+            .await;
+        self.popups.add_popup(text);
+    }
+
+    /// Removes the order with the number `id` from the current orders,
+    /// sending it on the passed `topic` and logging it as `action`.
+    /// Returns the current and the removed orders.
+    async fn remove(
+        &self,
+        id: u64,
+        topic: &Topic,
+        action: &str,
+        origin: &str,
+    ) -> (Vec<Order>, Vec<Order>) {
+        let mut current = self.current.lock().await;
+        let all = self.all.lock().await;
+        let removed = current
+            .iter()
+            .filter(|e| e.number == id)
+            .cloned()
+            .collect::<Vec<_>>();
+        current.retain(|e| e.number != id);
+        self.audit
+            .log(AuditEntry::mutation(
+                action,
+                origin,
+                Some(id),
+                serde_json::to_value(&removed).unwrap_or_default(),
+            ))
+            .await;
+        self.send_order_events(topic, &removed).await;
+        self.send_updates(&current, Vec::new(), removed.clone())
+            .await;
+        save(&current, &all);
+        (current.clone(), removed)
+    }
+
+    /// Sends each of the `orders` as single event on the `topic`.
+    async fn send_order_events(&self, topic: &Topic, orders: &[Order]) {
+        for order in orders {
+            if let Ok(order_json) = serde_json::to_string(order) {
+                let _ =
+                    (self.add_event)(topic.clone(), Box::new(move |_| Some(order_json.clone())))
+                        .await;
+            }
+        }
+    }
+
+    /// Sends the `current` orders on `orders_updated` and the changes on `orders_diff`.
+    async fn send_updates(&self, current: &[Order], added: Vec<Order>, removed: Vec<Order>) {
+        let current = current.to_vec();
+        let _ = (self.add_event)(
+            self.orders_updated.clone(),
+            Box::new(move |s| serde_json::to_string(&filter_orders(&current, s)).ok()),
+        )
+        .await;
+        let _ = (self.add_event)(
+            self.orders_diff.clone(),
+            Box::new(move |s| {
+                let diff = OrdersDiff {
+                    added: filter_orders(&added, s),
+                    removed: filter_orders(&removed, s),
+                };
+                // Nothing changed for subscribers filtering for other order types
+                if diff.added.is_empty() && diff.removed.is_empty() {
+                    return None;
+                }
+                serde_json::to_string(&diff).ok()
+            }),
+        )
+        .await;
+    }
+}
+
+/// Creates the routes to manage the `orders`.
+pub fn routes(orders: Arc<Orders>) -> Router {
     Router::new()
         .route("/:id", post(create_order))
         .route("/:id", delete(serve_order))
+        .route("/:id/cancel", post(cancel_order))
         .route("/", get(statistics))
         .route("/current", get(current_orders))
-        .with_state(Arc::new(OrderState {
-            add_event,
-            popups,
-            current: Arc::new(Mutex::new(current)),
-            all: Arc::new(Mutex::new(all)),
-        }))
+        .route("/ready", get(ready_orders))
+        .with_state(orders)
 }
 
 async fn create_order(
     Path(id): Path<u64>,
-    State(state): State<Arc<OrderState>>,
+    State(orders): State<Arc<Orders>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     body: String,
 ) -> impl IntoResponse {
-    let order = Order {
-        timestamp: SystemTime::UNIX_EPOCH
-            .elapsed()
-            .unwrap_or(Duration::default())
-            .as_millis(),
-        order_type: body,
-        number: id,
-    };
-    {
-        state.all.lock().await.push(order.clone());
-    };
-    let mut current_state = state.current.lock().await;
-    let all = state.all.lock().await;
-    current_state.push(order.clone());
-    if let Ok(current_state_json) = serde_json::to_string(&*current_state) {
-        let _ = (state.add_event)(
-            EventType::OrdersUpdated,
-            Box::new(move |_| current_state_json.clone()),
-        )
-        .await;
-    }
-    save(&current_state, &all);
-    Json(current_state.clone())
+    Json(
+        orders
+            .create(id, body, &get_origin(&headers, address))
+            .await,
+    )
 }
 
 async fn serve_order(
     Path(id): Path<u64>,
-    State(state): State<Arc<OrderState>>,
+    State(orders): State<Arc<Orders>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    {
-        state.popups.lock().await.add_popup(id.to_string())
-    };
-    let mut current = state.current.lock().await;
-    let all = state.all.lock().await;
-    current.retain(|e| e.number != id);
-    if let Ok(current_state_json) = serde_json::to_string(&*current) {
-        let _ = (state.add_event)(
-            EventType::OrdersUpdated,
-            Box::new(move |_| current_state_json.clone()),
-        )
-        .await;
+    let shown = orders.popups.has_displays().await;
+    (
+        AppendHeaders((!shown).then_some(NO_DISPLAY_WARNING)),
+        Json(orders.serve(id, &get_origin(&headers, address)).await),
+    )
+}
+
+async fn cancel_order(
+    Path(id): Path<u64>,
+    State(orders): State<Arc<Orders>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    Json(orders.cancel(id, &get_origin(&headers, address)).await)
+}
+
+/// Gets the origin of a request for the audit log,
+/// which is the station passed in the `X-Station` header or the IP address otherwise.
+fn get_origin(headers: &HeaderMap, address: SocketAddr) -> String {
+    headers
+        .get("X-Station")
+        .and_then(|h| h.to_str().ok())
+        .map(|station| station.to_owned())
+        .unwrap_or_else(|| address.ip().to_string())
+}
+
+/// Returns the orders with the types passed by the subscriber as `type` option,
+/// or all orders if the subscriber does not filter.
+fn filter_orders<'a>(orders: &'a [Order], subscriber: &Subscriber) -> Vec<&'a Order> {
+    match subscriber.options.get("type") {
+        Some(types) => {
+            let types = types.split(',').collect::<Vec<_>>();
+            orders
+                .iter()
+                .filter(|o| types.contains(&o.order_type.as_str()))
+                .collect()
+        }
+        None => orders.iter().collect(),
     }
-    save(&current, &all);
-    Json(current.clone())
 }
 
-async fn statistics(State(state): State<Arc<OrderState>>) -> impl IntoResponse {
-    Json(state.all.lock().await.clone())
+async fn statistics(State(orders): State<Arc<Orders>>) -> impl IntoResponse {
+    Json(orders.all.lock().await.clone())
+}
+
+async fn current_orders(State(orders): State<Arc<Orders>>) -> impl IntoResponse {
+    Json(orders.current.lock().await.clone())
 }
 
-async fn current_orders(State(state): State<Arc<OrderState>>) -> impl IntoResponse {
-    Json(state.current.lock().await.clone())
+async fn ready_orders(State(orders): State<Arc<Orders>>) -> impl IntoResponse {
+    Json(orders.ready.lock().await.clone())
 }
 
 fn save(current: &Vec<Order>, all: &Vec<Order>) {
@@ -0,0 +1,236 @@
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, sync::Arc};
+use tokio::sync::watch;
+
+use crate::{
+    cache::RemoteCache,
+    events::{EventAddFunction, Subscriber, Topics},
+    images::{self, Images},
+    settings::{PlaybackMode, Settings},
+    variants::Variants,
+};
+
+/// A named list of images shown on the displays of some groups.
+///
+/// Example: `{"name": "menu", "groups": ["entrance"], "path": "./menu.json", "image_timeout": 20}`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Playlist {
+    pub name: String,
+    /// Groups of the displays showing the playlist
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// The file containing the images, defaults to `./<name>.json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Generate the images from all images in this folder of the assets directory instead of reading them from the path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// How to show the board of the waiting and ready orders, not at all if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_board: Option<OrderBoard>,
+    #[serde(flatten)]
+    pub timing: Timing,
+}
+
+/// How a playlist shows the board of the waiting and ready orders.
+///
+/// Example: `{"every": 3}` or `"split"`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBoard {
+    /// As every nth slide, e.g. every third slide with `3`
+    Every(usize),
+    /// Next to every slide
+    Split,
+}
+
+/// The timing of a playlist, overriding the [Settings] changed at runtime.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct Timing {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_offset: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playback_mode: Option<PlaybackMode>,
+}
+
+impl Timing {
+    /// Returns the `settings` with the timing of the playlist applied.
+    pub fn apply(&self, settings: Settings) -> Settings {
+        Settings {
+            image_timeout: self.image_timeout.unwrap_or(settings.image_timeout),
+            image_offset: self.image_offset.unwrap_or(settings.image_offset),
+            playback_mode: self.playback_mode.unwrap_or(settings.playback_mode),
+            ..settings
+        }
+    }
+}
+
+/// Options shared by the slideshows of all playlists.
+pub struct SlideshowOptions {
+    /// Time in milliseconds between sending and showing an image
+    pub apply_delay: u64,
+    pub assets_dir: String,
+    /// Maximum size of uploaded images in bytes
+    pub upload_max_size: usize,
+    /// Local copies of remote images, not cached if `None`
+    pub cache: Option<Arc<RemoteCache>>,
+    /// Resized images for displays with a resolution, not resized if `None`
+    pub variants: Option<Arc<Variants>>,
+}
+
+/// Decides which playlist a display shows.
+#[derive(Debug)]
+pub struct Routing {
+    /// Names of the playlists by the display groups showing them
+    groups: BTreeMap<String, String>,
+    /// The playlist shown on displays without a group of any playlist
+    fallback: String,
+}
+
+impl Routing {
+    fn new(playlists: &[Playlist]) -> Self {
+        Routing {
+            groups: playlists
+                .iter()
+                .flat_map(|p| p.groups.iter().map(|g| (g.clone(), p.name.clone())))
+                .collect(),
+            fallback: playlists
+                .first()
+                .map(|p| p.name.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the name of the playlist the subscriber shows:
+    /// The one given with the `playlist` option when subscribing, the one of its group or the fallback.
+    pub fn playlist<'a>(&'a self, subscriber: &'a Subscriber) -> &'a str {
+        subscriber
+            .options
+            .get("playlist")
+            .or_else(|| {
+                subscriber
+                    .display
+                    .group
+                    .as_ref()
+                    .and_then(|g| self.groups.get(g))
+            })
+            .unwrap_or(&self.fallback)
+    }
+}
+
+/// Checks that the order board is shown as every `n`th slide with at least one other slide in between,
+/// as it would follow itself otherwise.
+pub fn check_order_board_every(n: usize) -> Result<usize, String> {
+    if n < 2 {
+        return Err("The order board must be shown at most every second slide".to_owned());
+    }
+    Ok(n)
+}
+
+/// Reads the playlists from `data`, checking that their names are unique and every group belongs to only one.
+pub fn parse(data: &str) -> Result<Vec<Playlist>, String> {
+    let playlists: Vec<Playlist> = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    if playlists.is_empty() {
+        return Err("No playlist configured".to_owned());
+    }
+    let mut groups = BTreeMap::new();
+    for (i, playlist) in playlists.iter().enumerate() {
+        if playlist.name.is_empty()
+            || !playlist
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("Invalid playlist name {:?}", playlist.name));
+        }
+        if playlists[..i].iter().any(|p| p.name == playlist.name) {
+            return Err(format!("Duplicate playlist {}", playlist.name));
+        }
+        if let Some(OrderBoard::Every(n)) = playlist.order_board {
+            check_order_board_every(n).map_err(|e| format!("{e} in playlist {}", playlist.name))?;
+        }
+        for group in &playlist.groups {
+            if let Some(other) = groups.insert(group.clone(), playlist.name.clone()) {
+                return Err(format!(
+                    "Group {group} belongs to playlists {other} and {}",
+                    playlist.name
+                ));
+            }
+        }
+    }
+    Ok(playlists)
+}
+
+/// Starts a slideshow for every playlist and creates their routes.
+/// The first playlist is shown on all displays without a group of any playlist.
+///
+/// - `GET /playlists`: All [Playlist]s
+/// - `/playlists/:name/...`: The routes of the images of the playlist, see [images::routes]
+/// - `/images/...`: The routes of the images of the first playlist
+pub async fn start(
+    playlists: Vec<Playlist>,
+    add_event: Arc<EventAddFunction>,
+    topics: &Topics,
+    settings: watch::Receiver<Settings>,
+    options: SlideshowOptions,
+) -> Router {
+    let routing = Arc::new(Routing::new(&playlists));
+    let mut router = Router::new();
+    for (i, playlist) in playlists.iter().enumerate() {
+        let images = Images::new(
+            add_event.clone(),
+            topics,
+            playlist,
+            routing.clone(),
+            settings.clone(),
+            &options,
+        );
+        let path = if let Some(folder) = &playlist.folder {
+            images
+                .generate(&options.assets_dir, folder)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to generate images of {}: {e}", playlist.name));
+            None
+        } else {
+            let path = playlist
+                .path
+                .clone()
+                .unwrap_or_else(|| format!("./{}.json", playlist.name));
+            let image_data = fs::read_to_string(&path).unwrap_or("[]".to_owned());
+            // Start without images, the validation report below tells what is wrong
+            if let Err(e) = images.set_images(&image_data).await {
+                println!("[Warning] Failed to read images from {path}: {e}");
+            }
+            images
+                .watch(path.clone())
+                .expect("Failed to watch image data");
+            Some(path)
+        };
+        let images = Arc::new(images);
+        images.run();
+        // Tell what is wrong with the images, as they are not shown otherwise
+        let (validated, validated_path) = (images.clone(), path.clone());
+        tokio::task::spawn(async move { validated.log_problems(validated_path.as_deref()).await });
+        let routes = images::routes(
+            images,
+            path,
+            options.assets_dir.clone(),
+            options.upload_max_size,
+        );
+        if i == 0 {
+            router = router.nest("/images", routes.clone());
+        }
+        router = router.nest(&format!("/playlists/{}", playlist.name), routes);
+    }
+    router.route(
+        "/playlists",
+        get(list_playlists).with_state(Arc::new(playlists)),
+    )
+}
+
+async fn list_playlists(State(playlists): State<Arc<Vec<Playlist>>>) -> impl IntoResponse {
+    Json(playlists.as_ref().clone())
+}
@@ -1,79 +1,131 @@
-use futures::{executor::block_on, lock::Mutex};
-use std::{collections::VecDeque, sync::Arc};
-use tokio::time::Interval;
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch,
+};
 
-use crate::events::{EventAddFunction, EventType};
+use crate::{
+    displays,
+    events::{EventAddFunction, Topic, Topics},
+    settings::Settings,
+};
+
+/// The data of a `popup_show` event.
+#[derive(Serialize)]
+struct PopupShow<'a> {
+    text: &'a str,
+    /// Time in milliseconds since the unix epoch at which the displays should show the popup
+    apply_at: u128,
+}
+
+/// The data of a `popup_hide` event.
+#[derive(Serialize)]
+struct PopupHide {
+    /// Time in milliseconds since the unix epoch at which the displays should hide the popup
+    apply_at: u128,
+}
 
 pub struct Popups {
-    popups: Arc<Mutex<VecDeque<String>>>,
-    show_timeout: Arc<Mutex<Interval>>,
-    wait_timeout: Arc<Mutex<Interval>>,
+    popups: UnboundedSender<String>,
+    /// The queued popups, taken by [Popups::run]
+    queue: Mutex<Option<UnboundedReceiver<String>>>,
+    settings: watch::Receiver<Settings>,
     add_event: Arc<EventAddFunction>,
+    popup_show: Topic,
+    popup_hide: Topic,
+    /// Whether to keep popups queued while no display is connected
+    hold: bool,
+    /// Time in milliseconds between sending and showing a popup
+    apply_delay: u64,
 }
 
 impl Popups {
-    pub fn new(add_event: Arc<EventAddFunction>, show_seconds: u64, wait_seconds: u64) -> Self {
+    /// Creates the popups, showing them for and waiting between them as long as the current `settings` say.
+    pub fn new(
+        add_event: Arc<EventAddFunction>,
+        topics: &Topics,
+        settings: watch::Receiver<Settings>,
+        hold: bool,
+        apply_delay: u64,
+    ) -> Self {
+        let (popups, queue) = mpsc::unbounded_channel();
         Self {
-            popups: Arc::new(Mutex::new(VecDeque::new())),
-            show_timeout: Arc::new(Mutex::new(tokio::time::interval(
-                tokio::time::Duration::from_secs(show_seconds),
-            ))),
-            wait_timeout: Arc::new(Mutex::new(tokio::time::interval(
-                tokio::time::Duration::from_secs(wait_seconds),
-            ))),
+            popups,
+            queue: Mutex::new(Some(queue)),
+            settings,
             add_event,
+            popup_show: topics.register("popup_show"),
+            popup_hide: topics.register("popup_hide"),
+            hold,
+            apply_delay,
         }
     }
 
-    pub fn add_popup(&mut self, popup: String) {
-        {
-            let mut popups = block_on(self.popups.lock());
-            popups.push_back(popup);
-            drop(popups);
-        };
+    pub fn add_popup(&self, popup: String) {
+        let _ = self.popups.send(popup);
     }
 
-    pub fn set_show_timeout(&mut self, seconds: u64) {
-        *block_on(self.show_timeout.lock()) =
-            tokio::time::interval(tokio::time::Duration::from_secs(seconds));
-    }
-
-    pub fn set_wait_timeout(&mut self, seconds: u64) {
-        *block_on(self.wait_timeout.lock()) =
-            tokio::time::interval(tokio::time::Duration::from_secs(seconds));
+    /// Returns whether any display would currently show a popup.
+    pub async fn has_displays(&self) -> bool {
+        self.popup_show.displays().await > 0
     }
 
+    /// Shows the queued popups one after another. Does nothing if called more than once.
     pub fn run(&self) -> tokio::task::JoinHandle<()> {
-        let popups = self.popups.clone();
-        let show_timeout = self.show_timeout.clone();
-        let wait_timeout = self.wait_timeout.clone();
+        let queue = self.queue.lock().ok().and_then(|mut q| q.take());
+        let settings = self.settings.clone();
         let add_event = self.add_event.clone();
+        let popup_show = self.popup_show.clone();
+        let popup_hide = self.popup_hide.clone();
+        let hold = self.hold;
+        let apply_delay = self.apply_delay;
         tokio::task::spawn(async move {
+            let Some(mut queue) = queue else {
+                return;
+            };
+            let mut held = None;
+            let mut holding = false;
             loop {
-                {
-                    let mut wait_timeout = wait_timeout.lock().await;
-                    wait_timeout.reset();
-                    wait_timeout.tick().await;
+                let wait = Duration::from_secs(settings.borrow().popup_wait);
+                tokio::time::sleep(wait).await;
+                let popup = match held.take() {
+                    Some(popup) => popup,
+                    None => match queue.recv().await {
+                        Some(popup) => popup,
+                        None => return,
+                    },
                 };
-                {
-                    let popup = popups.lock().await.pop_front();
-                    // let popup = popups.;
-                    if let Some(popup) = popup {
-                        let _ =
-                            add_event(EventType::PopupShow, Box::new(move |_| popup.clone())).await;
-                    } else {
-                        // Wait for next popup
+                let apply_at = displays::now() + u128::from(apply_delay);
+                let shown = serde_json::to_string(&PopupShow {
+                    text: &popup,
+                    apply_at,
+                })
+                .ok();
+                let report = add_event(popup_show.clone(), Box::new(move |_| shown.clone())).await;
+                if report.delivered == 0 {
+                    if hold {
+                        if !holding {
+                            println!("[Warning] No display connected, holding back popups");
+                            holding = true;
+                        }
+                        held = Some(popup);
                         continue;
                     }
-                };
-                {
-                    let mut show_timeout = show_timeout.lock().await;
-                    show_timeout.reset();
-                    show_timeout.tick().await;
-                };
-                {
-                    let _ = add_event(EventType::PopupHide, Box::new(move |_| String::new())).await;
-                };
+                    println!("[Warning] No display connected, popup {popup} was not shown");
+                }
+                holding = false;
+                let show = Duration::from_secs(settings.borrow().popup_show);
+                tokio::time::sleep(show).await;
+                // Hide the popup as long after it was shown as it should be shown
+                let hidden = serde_json::to_string(&PopupHide {
+                    apply_at: apply_at + show.as_millis(),
+                })
+                .ok();
+                let _ = add_event(popup_hide.clone(), Box::new(move |_| hidden.clone())).await;
             }
         })
     }
@@ -0,0 +1,141 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// When and how long to show an image. All times are local times of the server.
+///
+/// Example: `{"duration": 20, "days": ["mon", "fri"], "dayparts": [{"from": "11:00", "until": "14:00"}]}`
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Schedule {
+    /// Time to show the image for in seconds, defaults to the image timeout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// Time from which on the image is shown, e.g. `2024-06-01T18:00:00`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<NaiveDateTime>,
+    /// Time after which the image is no longer shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<NaiveDateTime>,
+    /// Days of the week to show the image on, e.g. `mon` or `monday`, all days if empty.
+    /// Dayparts spanning midnight belong to the day they start on.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<Weekday>,
+    /// Times of the day to show the image at, the whole day if empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dayparts: Vec<Daypart>,
+}
+
+/// A time of the day, e.g. `{"from": "11:00", "until": "14:00"}`.
+/// Spans midnight if `until` is before `from`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Daypart {
+    pub from: NaiveTime,
+    pub until: NaiveTime,
+}
+
+impl Schedule {
+    /// Returns whether the image should be shown at the time `now`.
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        self.valid_from.is_none_or(|from| from <= now)
+            && self.valid_until.is_none_or(|until| now < until)
+            && if self.dayparts.is_empty() {
+                self.is_shown_on(now.weekday())
+            } else {
+                self.dayparts
+                    .iter()
+                    .any(|d| d.started_on(now).is_some_and(|day| self.is_shown_on(day)))
+            }
+    }
+
+    fn is_shown_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+}
+
+impl Daypart {
+    /// Returns the day the daypart containing `now` started on, or `None` if `now` is outside of it.
+    fn started_on(&self, now: NaiveDateTime) -> Option<Weekday> {
+        let time = now.time();
+        if self.from <= self.until {
+            (self.from <= time && time < self.until).then(|| now.weekday())
+        } else if self.from <= time {
+            Some(now.weekday())
+        } else {
+            // After midnight, so it started the day before
+            (time < self.until).then(|| now.weekday().pred())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        s.parse().unwrap()
+    }
+
+    fn daypart(from: &str, until: &str) -> Daypart {
+        Daypart {
+            from: from.parse().unwrap(),
+            until: until.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn daypart_within_day() {
+        let lunch = daypart("11:00:00", "14:00:00");
+        // 2024-06-07 is a friday
+        assert_eq!(
+            lunch.started_on(time("2024-06-07T11:00:00")),
+            Some(Weekday::Fri)
+        );
+        assert_eq!(
+            lunch.started_on(time("2024-06-07T13:59:59")),
+            Some(Weekday::Fri)
+        );
+        assert_eq!(lunch.started_on(time("2024-06-07T14:00:00")), None);
+        assert_eq!(lunch.started_on(time("2024-06-07T10:59:59")), None);
+    }
+
+    #[test]
+    fn daypart_across_midnight() {
+        let night = daypart("22:00:00", "02:00:00");
+        assert_eq!(
+            night.started_on(time("2024-06-07T23:30:00")),
+            Some(Weekday::Fri)
+        );
+        assert_eq!(
+            night.started_on(time("2024-06-08T01:30:00")),
+            Some(Weekday::Fri)
+        );
+        assert_eq!(night.started_on(time("2024-06-08T02:00:00")), None);
+        assert_eq!(night.started_on(time("2024-06-07T21:59:59")), None);
+    }
+
+    #[test]
+    fn schedule_across_midnight() {
+        let schedule = Schedule {
+            days: vec![Weekday::Fri],
+            dayparts: vec![daypart("22:00:00", "02:00:00")],
+            ..Default::default()
+        };
+        assert!(schedule.is_active(time("2024-06-07T22:00:00")));
+        assert!(schedule.is_active(time("2024-06-08T01:00:00")));
+        assert!(!schedule.is_active(time("2024-06-07T01:00:00")));
+        assert!(!schedule.is_active(time("2024-06-08T22:00:00")));
+    }
+
+    #[test]
+    fn schedule_validity() {
+        let schedule = Schedule {
+            valid_from: Some(time("2024-06-01T18:00:00")),
+            valid_until: Some(time("2024-06-02T00:00:00")),
+            ..Default::default()
+        };
+        assert!(!schedule.is_active(time("2024-06-01T17:59:59")));
+        assert!(schedule.is_active(time("2024-06-01T18:00:00")));
+        assert!(!schedule.is_active(time("2024-06-02T00:00:00")));
+        assert!(Schedule::default().is_active(time("2024-06-02T00:00:00")));
+    }
+}
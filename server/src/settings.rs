@@ -0,0 +1,140 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, sync::Arc};
+use tokio::sync::watch;
+
+/// The highest offset of images between displays, so offsets of many displays cannot overflow
+const MAX_IMAGE_OFFSET: usize = 1000;
+
+/// Creates the routes to change the `settings` at runtime, persisting them to the file at `path`.
+/// Popups must be waited for between them if they are held back while no display is connected (`popup_hold`),
+/// as they would be retried without a pause otherwise.
+///
+/// - `GET /`: The current [Settings]
+/// - `PUT /`: Changes the settings given in the body, responding with the new [Settings].
+///   Invalid values are rejected with `422 Unprocessable Entity` and an object mapping each invalid field to its error.
+///   Responds with `500 Internal Server Error` without changing the settings if they cannot be saved.
+pub fn routes(settings: watch::Sender<Settings>, path: String, popup_hold: bool) -> Router {
+    Router::new()
+        .route("/", get(get_settings).put(change_settings))
+        .with_state(Arc::new(SettingsState {
+            settings,
+            path,
+            popup_hold,
+        }))
+}
+
+/// Settings that can be changed while running.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    /// Timeout of images without their own duration in seconds
+    pub image_timeout: u64,
+    /// Offset of images between displays without a configured offset
+    pub image_offset: usize,
+    /// Time to show popups for in seconds
+    pub popup_show: u64,
+    /// Time to wait between popups in seconds
+    pub popup_wait: u64,
+    pub playback_mode: PlaybackMode,
+}
+
+/// The order to show the images in.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackMode {
+    /// In the order of the images
+    #[default]
+    Sequential,
+    /// In a random order, showing every image once before reshuffling
+    Shuffle,
+    /// Showing images more often the higher their weight is
+    Weighted,
+}
+
+/// A change of some [Settings].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettingsChange {
+    image_timeout: Option<u64>,
+    image_offset: Option<usize>,
+    popup_show: Option<u64>,
+    popup_wait: Option<u64>,
+    playback_mode: Option<PlaybackMode>,
+}
+
+struct SettingsState {
+    settings: watch::Sender<Settings>,
+    path: String,
+    popup_hold: bool,
+}
+
+impl Settings {
+    /// Reads the settings persisted at `path`, using the `defaults` for any that are missing.
+    pub fn load(path: &str, defaults: Settings) -> Settings {
+        let Ok(data) = fs::read_to_string(path) else {
+            return defaults;
+        };
+        let change = serde_json::from_str::<SettingsChange>(&data).unwrap_or_else(|e| {
+            println!("[Warning] Failed to read settings from {path}, using the arguments: {e}");
+            SettingsChange {
+                image_timeout: None,
+                image_offset: None,
+                popup_show: None,
+                popup_wait: None,
+                playback_mode: None,
+            }
+        });
+        defaults.apply(change)
+    }
+
+    fn apply(self, change: SettingsChange) -> Settings {
+        Settings {
+            image_timeout: change.image_timeout.unwrap_or(self.image_timeout),
+            image_offset: change.image_offset.unwrap_or(self.image_offset),
+            popup_show: change.popup_show.unwrap_or(self.popup_show),
+            popup_wait: change.popup_wait.unwrap_or(self.popup_wait),
+            playback_mode: change.playback_mode.unwrap_or(self.playback_mode),
+        }
+    }
+}
+
+async fn get_settings(State(state): State<Arc<SettingsState>>) -> impl IntoResponse {
+    Json(*state.settings.borrow())
+}
+
+async fn change_settings(
+    State(state): State<Arc<SettingsState>>,
+    Json(change): Json<SettingsChange>,
+) -> axum::response::Response {
+    let settings = state.settings.borrow().apply(change);
+    let mut errors = BTreeMap::new();
+    if settings.image_timeout == 0 {
+        errors.insert("image_timeout", "Must be positive".to_owned());
+    }
+    if settings.image_offset > MAX_IMAGE_OFFSET {
+        errors.insert(
+            "image_offset",
+            format!("Must be at most {MAX_IMAGE_OFFSET}"),
+        );
+    }
+    if settings.popup_show == 0 {
+        errors.insert("popup_show", "Must be positive".to_owned());
+    }
+    if settings.popup_wait == 0 && state.popup_hold {
+        errors.insert(
+            "popup_wait",
+            "Must be positive while popups are held back".to_owned(),
+        );
+    }
+    if !errors.is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response();
+    }
+    let data = serde_json::to_string_pretty(&settings).unwrap_or_else(|_| "{}".to_owned());
+    if let Err(e) = fs::write(&state.path, data) {
+        println!("[Warning] Failed to save settings");
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    // Does not block, the slideshow applies the settings immediately and the popups with the next popup
+    state.settings.send_replace(settings);
+    Json(settings).into_response()
+}
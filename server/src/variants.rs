@@ -0,0 +1,268 @@
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use image::{imageops::FilterType, ImageFormat, ImageReader};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::sync::Semaphore;
+
+use crate::displays::Resolution;
+
+/// Quality of the lossy WebP variants, from 0 to 100
+const WEBP_QUALITY: f32 = 80.0;
+
+/// Creates the routes to get resized variants of the images in the assets directory.
+///
+/// - `GET /:resolution/*path`: The image at `path` of the assets directory, scaled down to cover the `resolution`
+///   (e.g. `1920x1080`). Generates the variant if it does not exist yet
+///   and redirects to the original if the image does not need to or cannot be resized.
+///   Only resolutions of configured displays are served, so others cannot fill the disk with variants.
+pub fn routes(variants: Arc<Variants>) -> Router {
+    Router::new()
+        .route("/:resolution/*path", get(get_variant))
+        .with_state(variants)
+}
+
+/// Variants of the images in the assets directory scaled down to the resolutions of the displays,
+/// so displays do not have to decode large images. The originals are left untouched.
+///
+/// Variants are stored as lossy WebP at `<directory>/<resolution>/<path in the assets directory>.webp`
+/// and regenerated when the original changes.
+/// Images that are not larger than the resolution or whose variant would not be smaller get no variant.
+pub struct Variants {
+    assets_dir: PathBuf,
+    directory: PathBuf,
+    /// The resolutions of the configured displays, the only ones variants are generated for
+    resolutions: HashSet<Resolution>,
+    /// Paths of the variants currently being generated, without the `.webp` extension
+    pending: Mutex<HashSet<PathBuf>>,
+    /// Modification times of the originals of the variants that were not generated by their path without `.webp`,
+    /// to only try again when the original changes
+    skipped: Mutex<HashMap<PathBuf, SystemTime>>,
+    /// Limits how many variants are generated at once, as it takes a lot of memory and CPU time
+    generating: Semaphore,
+}
+
+impl Variants {
+    /// Creates the variants of the images in the `assets_dir` for the `resolutions`, stored in the `directory`.
+    pub fn new(assets_dir: &str, directory: &str, resolutions: HashSet<Resolution>) -> Self {
+        Self {
+            assets_dir: PathBuf::from(assets_dir),
+            directory: PathBuf::from(directory),
+            resolutions,
+            pending: Mutex::new(HashSet::new()),
+            skipped: Mutex::new(HashMap::new()),
+            generating: Semaphore::new(1),
+        }
+    }
+
+    /// Returns the URL of the variant of the image at `url` for the `resolution`,
+    /// if it is in the assets directory and the variant is up to date.
+    pub fn url(&self, url: &str, resolution: Resolution) -> Option<String> {
+        let path = asset_path(url)?;
+        self.current(&path, resolution)
+            .map(|_| format!("/variants/{resolution}/{path}"))
+    }
+
+    /// Generates the missing or outdated variants of the images at `urls` for the `resolution` in the background.
+    pub fn prefetch<'a>(
+        self: &Arc<Self>,
+        urls: impl IntoIterator<Item = &'a str>,
+        resolution: Resolution,
+    ) {
+        if !self.resolutions.contains(&resolution) {
+            return;
+        }
+        for path in urls.into_iter().filter_map(asset_path) {
+            if self.is_skipped(&path, resolution)
+                || self.current(&path, resolution).is_some()
+                || !self.reserve(&path, resolution)
+            {
+                continue;
+            }
+            let variants = self.clone();
+            tokio::task::spawn(async move {
+                if let Err(e) = variants.generate(&path, resolution).await {
+                    println!("[Warning] Failed to resize {path} to {resolution}: {e}");
+                }
+            });
+        }
+    }
+
+    /// Returns the path of the variant of the asset at `path` for the `resolution`,
+    /// if it exists and is newer than the original.
+    fn current(&self, path: &str, resolution: Resolution) -> Option<PathBuf> {
+        let original = modified(&self.assets_dir.join(path))?;
+        let variant = append_extension(&self.variant_path(path, resolution), "webp");
+        modified(&variant)
+            .is_some_and(|m| m >= original)
+            .then_some(variant)
+    }
+
+    /// Returns whether the asset at `path` got no variant for the `resolution` and did not change since.
+    fn is_skipped(&self, path: &str, resolution: Resolution) -> bool {
+        let variant = self.variant_path(path, resolution);
+        self.skipped.lock().is_ok_and(|s| {
+            s.get(&variant)
+                .is_some_and(|t| Some(*t) == modified(&self.assets_dir.join(path)))
+        })
+    }
+
+    /// Returns the path of the variant of the asset at `path` for the `resolution`, without the `.webp` extension.
+    fn variant_path(&self, path: &str, resolution: Resolution) -> PathBuf {
+        self.directory.join(resolution.to_string()).join(path)
+    }
+
+    /// Marks the variant of the asset at `path` for the `resolution` as being generated.
+    /// Returns `false` if it already is.
+    fn reserve(&self, path: &str, resolution: Resolution) -> bool {
+        let variant = self.variant_path(path, resolution);
+        self.pending.lock().is_ok_and(|mut p| p.insert(variant))
+    }
+
+    /// Generates the variant of the asset at `path` for the `resolution`, which must be reserved,
+    /// and returns the path of the variant, or `None` if the original is shown instead.
+    async fn generate(
+        &self,
+        path: &str,
+        resolution: Resolution,
+    ) -> Result<Option<PathBuf>, String> {
+        let variant = self.variant_path(path, resolution);
+        let result = async {
+            let _permit = self.generating.acquire().await.map_err(|e| e.to_string())?;
+            let original = self.assets_dir.join(path);
+            let target = variant.clone();
+            tokio::task::spawn_blocking(move || resize(&original, &target, resolution))
+                .await
+                .map_err(|e| e.to_string())?
+        }
+        .await;
+        if let (Err(_) | Ok(None), Some(original), Ok(mut skipped)) = (
+            &result,
+            modified(&self.assets_dir.join(path)),
+            self.skipped.lock(),
+        ) {
+            skipped.insert(variant.clone(), original);
+        }
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&variant);
+        }
+        result
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Appends the `extension` to the `path`, keeping its current extension.
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+/// Returns the path in the assets directory of the image at `url`,
+/// if it is there and in a format that can be resized.
+fn asset_path(url: &str) -> Option<String> {
+    let path = url.strip_prefix("/assets/")?;
+    let safe = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    let supported = matches!(
+        ImageFormat::from_path(path),
+        Ok(ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)
+    );
+    (safe && supported).then(|| path.to_owned())
+}
+
+/// Scales the image at `original` down to cover the `resolution` and stores it as lossy WebP at `target` with `.webp` appended.
+/// Returns the path of the variant, or `None` if the image is not larger than the resolution
+/// or the variant would not be smaller than the original.
+fn resize(
+    original: &Path,
+    target: &Path,
+    resolution: Resolution,
+) -> Result<Option<PathBuf>, String> {
+    let image = ImageReader::open(original)
+        .map_err(|e| e.to_string())?
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .decode()
+        .map_err(|e| e.to_string())?;
+    // Cover the display like the slideshow does, so nothing is upscaled by it
+    let scale = f64::max(
+        f64::from(resolution.width) / f64::from(image.width()),
+        f64::from(resolution.height) / f64::from(image.height()),
+    );
+    if scale >= 1.0 {
+        return Ok(None);
+    }
+    let image = image.resize_exact(
+        (f64::from(image.width()) * scale).round() as u32,
+        (f64::from(image.height()) * scale).round() as u32,
+        FilterType::CatmullRom,
+    );
+    let data = if image.color().has_alpha() {
+        let image = image.to_rgba8();
+        webp::Encoder::from_rgba(&image, image.width(), image.height()).encode(WEBP_QUALITY)
+    } else {
+        let image = image.to_rgb8();
+        webp::Encoder::from_rgb(&image, image.width(), image.height()).encode(WEBP_QUALITY)
+    };
+    let original_size = fs::metadata(original).map_err(|e| e.to_string())?.len();
+    if data.len() as u64 >= original_size {
+        return Ok(None);
+    }
+    if let Some(directory) = target.parent() {
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    }
+    // Write to a temporary file first, so displays never get a partial file
+    let part = append_extension(target, "part");
+    let target = append_extension(target, "webp");
+    fs::write(&part, &*data).map_err(|e| e.to_string())?;
+    fs::rename(&part, &target).map_err(|e| e.to_string())?;
+    Ok(Some(target))
+}
+
+async fn get_variant(
+    State(variants): State<Arc<Variants>>,
+    UrlPath((resolution, path)): UrlPath<(String, String)>,
+) -> Response {
+    let resolution = resolution.parse::<Resolution>().ok();
+    let Some(resolution) = resolution.filter(|r| variants.resolutions.contains(r)) else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+    let url = format!("/assets/{path}");
+    let Some(path) = asset_path(&url) else {
+        return Redirect::temporary(&url).into_response();
+    };
+    let variant = if let Some(variant) = variants.current(&path, resolution) {
+        Ok(Some(variant))
+    } else if !variants.is_skipped(&path, resolution) && variants.reserve(&path, resolution) {
+        variants.generate(&path, resolution).await
+    } else {
+        Ok(None)
+    };
+    let data = variant.ok().flatten().and_then(|v| {
+        let content_type = mime_guess::from_path(&v).first_or_octet_stream();
+        Some((content_type.to_string(), fs::read(v).ok()?))
+    });
+    match data {
+        Some((content_type, data)) => {
+            ([(header::CONTENT_TYPE, content_type)], data).into_response()
+        }
+        None => Redirect::temporary(&url).into_response(),
+    }
+}
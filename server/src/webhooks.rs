@@ -0,0 +1,248 @@
+use futures::lock::Mutex;
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+use crate::{displays, events::Topics};
+
+/// Time to wait before retrying a failed delivery for the first time.
+/// Doubles with every further failure up to [MAX_BACKOFF].
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of undelivered events kept per webhook, older ones are dropped first
+const MAX_OUTBOX_SIZE: usize = 1000;
+/// Time to collect changes to the outbox before saving it, so it is not rewritten for every event
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A configured outbound webhook.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Webhook {
+    /// The URL the events are `POST`ed to
+    pub url: String,
+    /// The topics to send, may contain `*` as wildcard
+    pub topics: Vec<String>,
+    /// If set, the body is signed using HMAC-SHA256 with this secret.
+    /// The signature is sent hex-encoded as `X-Pizzplay-Signature: sha256=<signature>`.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// The JSON body sent to a webhook.
+#[derive(Serialize)]
+struct Payload<'a> {
+    topic: &'a str,
+    /// Time of the event in milliseconds since the unix epoch
+    timestamp: u128,
+    /// The data of the event, as JSON if possible or as string otherwise
+    data: serde_json::Value,
+}
+
+/// Bodies waiting to be delivered by the URL of their webhook.
+type Outbox = BTreeMap<String, VecDeque<String>>;
+
+/// Why a delivery failed.
+enum Failure {
+    /// The webhook might accept the body later, e.g. because it is unreachable
+    Retry(String),
+    /// The webhook rejected the body and would reject it again
+    Rejected(String),
+}
+
+/// Sends events to outbound webhooks.
+///
+/// Events are first written to a persistent outbox,
+/// so they are not lost while a webhook is unreachable or the server restarts.
+/// Failed deliveries are retried with exponential backoff, keeping the order of events.
+/// Events rejected by the webhook with a client error are dropped, as retrying them would block all later events.
+pub struct Webhooks {
+    webhooks: Vec<Webhook>,
+    outbox: Arc<Mutex<Outbox>>,
+    outbox_path: Arc<str>,
+    /// Notified whenever the outbox changed and needs to be saved
+    changed: Arc<Notify>,
+}
+
+impl Webhooks {
+    /// Reads the webhooks from a JSON list of [Webhook]s
+    /// and restores undelivered events from the outbox at `outbox_path`.
+    /// Fails if a URL is configured more than once, as the outbox keeps the events by URL.
+    pub fn new(webhooks: &str, outbox_path: &str) -> Result<Self, String> {
+        let webhooks = serde_json::from_str::<Vec<Webhook>>(webhooks).map_err(|e| e.to_string())?;
+        for (i, webhook) in webhooks.iter().enumerate() {
+            if webhooks[..i].iter().any(|w| w.url == webhook.url) {
+                return Err(format!("Duplicate webhook URL {}", webhook.url));
+            }
+        }
+        let outbox = fs::read_to_string(outbox_path).unwrap_or_else(|_| "{}".to_owned());
+        let mut outbox = serde_json::from_str::<Outbox>(&outbox).unwrap_or_else(|_| {
+            println!("[Warning] Failed to read webhook outbox, discarding it");
+            Outbox::new()
+        });
+        outbox.retain(|url, _| webhooks.iter().any(|w| &w.url == url));
+        Ok(Self {
+            webhooks,
+            outbox: Arc::new(Mutex::new(outbox)),
+            outbox_path: outbox_path.into(),
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Subscribes the webhooks to their topics and starts delivering events.
+    /// Fails if a webhook subscribes to an unknown topic.
+    ///
+    /// Must be called after all topics have been registered.
+    pub async fn run(&self, topics: &Topics) -> Result<(), String> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        // Save the outbox after changes
+        let outbox = self.outbox.clone();
+        let outbox_path = self.outbox_path.clone();
+        let changed = self.changed.clone();
+        tokio::task::spawn(async move {
+            loop {
+                changed.notified().await;
+                tokio::time::sleep(SAVE_INTERVAL).await;
+                save(&outbox_path, &*outbox.lock().await);
+            }
+        });
+
+        for webhook in self.webhooks.iter().cloned() {
+            let mut events = topics.subscribe(&webhook.topics).await?;
+            let pending = Arc::new(Notify::new());
+
+            // Queue events in the outbox
+            let outbox = self.outbox.clone();
+            let changed = self.changed.clone();
+            let url = webhook.url.clone();
+            let notify = pending.clone();
+            tokio::task::spawn(async move {
+                while let Some((topic, data)) = events.recv().await {
+                    let payload = Payload {
+                        topic: &topic,
+                        timestamp: displays::now(),
+                        data: serde_json::from_str(&data)
+                            .unwrap_or(serde_json::Value::String(data)),
+                    };
+                    let Ok(body) = serde_json::to_string(&payload) else {
+                        continue;
+                    };
+                    let mut outbox = outbox.lock().await;
+                    let bodies = outbox.entry(url.clone()).or_default();
+                    bodies.push_back(body);
+                    if bodies.len() > MAX_OUTBOX_SIZE {
+                        bodies.pop_front();
+                        println!(
+                            "[Warning] Webhook outbox of {url} is full, dropping the oldest event"
+                        );
+                    }
+                    changed.notify_one();
+                    notify.notify_one();
+                }
+            });
+
+            // Deliver events from the outbox
+            let outbox = self.outbox.clone();
+            let changed = self.changed.clone();
+            let client = client.clone();
+            tokio::task::spawn(async move {
+                let mut backoff = INITIAL_BACKOFF;
+                loop {
+                    let body = {
+                        outbox
+                            .lock()
+                            .await
+                            .get(&webhook.url)
+                            .and_then(|bodies| bodies.front().cloned())
+                    };
+                    let Some(body) = body else {
+                        pending.notified().await;
+                        continue;
+                    };
+                    let result = deliver(&client, &webhook, &body).await;
+                    if let Err(Failure::Rejected(e)) = &result {
+                        println!(
+                            "[Warning] Webhook {} rejected event, dropping it: {e}\n{body}",
+                            webhook.url
+                        );
+                    }
+                    match result {
+                        Ok(()) | Err(Failure::Rejected(_)) => {
+                            let mut outbox = outbox.lock().await;
+                            // The body might have been dropped while delivering, as the outbox was full
+                            if let Some(bodies) = outbox.get_mut(&webhook.url) {
+                                if bodies.front() == Some(&body) {
+                                    bodies.pop_front();
+                                }
+                            }
+                            changed.notify_one();
+                            backoff = INITIAL_BACKOFF;
+                        }
+                        Err(Failure::Retry(e)) => {
+                            println!(
+                                "[Warning] Failed to deliver webhook to {}, retrying in {}s: {e}",
+                                webhook.url,
+                                backoff.as_secs()
+                            );
+                            tokio::time::sleep(backoff).await;
+                            backoff = (backoff * 2).min(MAX_BACKOFF);
+                        }
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Sends the `body` to the `webhook`, signing it if the webhook has a secret.
+async fn deliver(client: &reqwest::Client, webhook: &Webhook, body: &str) -> Result<(), Failure> {
+    let mut request = client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let Some(secret) = &webhook.secret {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .map_err(|e| Failure::Rejected(e.to_string()))?;
+        mac.update(body.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        request = request.header("X-Pizzplay-Signature", format!("sha256={signature}"));
+    }
+    let status = request
+        .body(body.to_owned())
+        .send()
+        .await
+        .map_err(|e| Failure::Retry(e.to_string()))?
+        .status();
+    let retryable = matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+    );
+    if status.is_success() {
+        Ok(())
+    } else if status.is_client_error() && !retryable {
+        Err(Failure::Rejected(status.to_string()))
+    } else {
+        Err(Failure::Retry(status.to_string()))
+    }
+}
+
+fn save(path: &str, outbox: &Outbox) {
+    let r = fs::write(
+        path,
+        serde_json::to_string(outbox).unwrap_or_else(|_| "{}".to_owned()),
+    );
+    if r.is_err() {
+        println!("[Warning] Failed to save webhook outbox");
+    }
+}